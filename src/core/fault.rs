@@ -0,0 +1,111 @@
+use rand::{rngs::StdRng, Rng, SeedableRng};
+
+/// When an injected fault fires.
+///
+/// Faults count the occurrences of what they act on, e.g. the bytes on an
+/// I2C bus or the clock cycles of a SPI transfer, starting at zero.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Trigger {
+    /// Only on the given occurrence.
+    Nth(u64),
+    /// On every occurrence from the given one on.
+    From(u64),
+    /// On every occurrence with the given probability, drawn from the seeded
+    /// generator of the [FaultPlan].
+    Chance(f64)
+}
+
+/// The faults to inject into a bus or link and when, along with the ones
+/// that fired so far.
+///
+/// Random triggers draw from a generator seeded by the plan, so two runs with
+/// the same seed inject the same faults at the same points.
+pub struct FaultPlan<F> {
+    faults: Vec<(F, Trigger)>,
+    rng: StdRng,
+    /// Every fault that fired, with the occurrence it fired on.
+    fired: Vec<(F, u64)>
+}
+
+impl<F: Clone> FaultPlan<F> {
+    /// Creates an empty plan seeded with zero.
+    pub fn new() -> Self {
+        Self::seeded(0)
+    }
+    /// Creates an empty plan whose random triggers draw from the given seed.
+    pub fn seeded(seed: u64) -> Self {
+        Self {
+            faults: vec![],
+            rng: StdRng::seed_from_u64(seed),
+            fired: vec![]
+        }
+    }
+    /// Adds a fault.
+    ///
+    /// Panics if a [Trigger::Chance] is not a probability between 0 and 1.
+    pub fn with(mut self, fault: F, trigger: Trigger) -> Self {
+        if let Trigger::Chance(probability) = trigger {
+            assert!((0.0..=1.0).contains(&probability), "A chance of {} is not a probability.", probability);
+        }
+        self.faults.push((fault, trigger));
+        self
+    }
+    /// Every fault that fired so far, with the occurrence it fired on.
+    pub fn fired(&self) -> &[(F, u64)] {
+        &self.fired
+    }
+    /// The faults firing on an occurrence, in the order they were added.
+    pub(crate) fn roll(&mut self, occurrence: u64) -> Vec<F> {
+        let mut firing = vec![];
+        for (fault, trigger) in &self.faults {
+            let fires = match *trigger {
+                Trigger::Nth(n) => occurrence == n,
+                Trigger::From(n) => occurrence >= n,
+                Trigger::Chance(probability) => self.rng.random_bool(probability)
+            };
+            if fires {
+                firing.push(fault.clone());
+                self.fired.push((fault.clone(), occurrence));
+            }
+        }
+        firing
+    }
+}
+
+impl<F: Clone> Default for FaultPlan<F> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{FaultPlan, Trigger};
+
+    #[test]
+    pub fn test_triggers() {
+        let mut plan = FaultPlan::new()
+            .with('n', Trigger::Nth(1))
+            .with('f', Trigger::From(3));
+        let fired: Vec<Vec<char>> = (0..5).map(|i| plan.roll(i)).collect();
+        assert_eq!(fired, vec![vec![], vec!['n'], vec![], vec!['f'], vec!['f']]);
+        assert_eq!(plan.fired(), &[('n', 1), ('f', 3), ('f', 4)]);
+
+        // The same seed gives the same faults.
+        let run = |seed: u64| {
+            let mut plan = FaultPlan::seeded(seed).with((), Trigger::Chance(0.3));
+            (0..100).for_each(|i| { plan.roll(i); });
+            plan.fired().iter().map(|(_, at)| *at).collect::<Vec<_>>()
+        };
+        assert_eq!(run(7), run(7));
+        assert_ne!(run(7), run(8));
+        assert!(!run(7).is_empty() && run(7).len() < 100);
+
+        // Chances outside of 0 to 1 are refused when the plan is made.
+        for chance in [-0.1, 1.5, f64::NAN] {
+            assert!(std::panic::catch_unwind(|| FaultPlan::new().with((), Trigger::Chance(chance))).is_err());
+        }
+        let mut plan = FaultPlan::new().with('a', Trigger::Chance(1.0)).with('z', Trigger::Chance(0.0));
+        assert_eq!(plan.roll(0), vec!['a']);
+    }
+}
@@ -0,0 +1,40 @@
+use std::fmt::{self, Display};
+
+/// The first transaction where a run went different from a golden trace.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct GoldenMismatch {
+    /// The index of the transaction, counted from 0.
+    pub index: usize,
+    /// The transaction in the golden trace, if it has that many.
+    pub expected: Option<String>,
+    /// The transaction of the run, if it had that many.
+    pub actual: Option<String>
+}
+
+impl GoldenMismatch {
+    /// Compares two runs transaction by transaction, `key` picks out what has
+    /// to match and `render` turns a transaction into something readable.
+    pub(crate) fn compare<T, K: PartialEq>(
+        expected: &[T],
+        actual: &[T],
+        key: impl Fn(&T) -> K,
+        render: impl Fn(&T) -> String
+    ) -> Result<(), GoldenMismatch> {
+        for index in 0..expected.len().max(actual.len()) {
+            let (e, a) = (expected.get(index), actual.get(index));
+            if e.map(&key) != a.map(&key) {
+                return Err(GoldenMismatch { index, expected: e.map(&render), actual: a.map(&render) });
+            }
+        }
+        Ok(())
+    }
+}
+
+impl Display for GoldenMismatch {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let or_nothing = |t: &Option<String>| t.clone().unwrap_or_else(|| "nothing".to_string());
+        write!(f, "transaction {} differs\nexpected: {}\n  actual: {}", self.index, or_nothing(&self.expected), or_nothing(&self.actual))
+    }
+}
+
+impl std::error::Error for GoldenMismatch {}
@@ -0,0 +1,151 @@
+use std::{fs::File, io::{self, BufWriter, Write}, path::Path, time::Duration};
+
+/// A uniformly sampled capture of a few digital lines, the way a logic
+/// analyzer takes it.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct LogicTrace {
+    /// What the lines belong to, e.g. `i2c`.
+    name: String,
+    channels: Vec<String>,
+    /// The time between two samples.
+    period: Duration,
+    /// One word per sample, bit `n` holds the level of channel `n`.
+    samples: Vec<u32>
+}
+
+impl LogicTrace {
+    /// Creates an empty trace, at most 32 channels fit.
+    pub fn new(name: impl Into<String>, channels: &[&str], period: Duration) -> Self {
+        assert!(channels.len() <= 32, "a trace holds at most 32 channels");
+        Self {
+            name: name.into(),
+            channels: channels.iter().map(|c| c.to_string()).collect(),
+            period,
+            samples: vec![]
+        }
+    }
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+    pub fn channels(&self) -> &[String] {
+        &self.channels
+    }
+    /// The index of a channel by its name.
+    pub fn channel(&self, name: &str) -> Option<usize> {
+        self.channels.iter().position(|c| c == name)
+    }
+    /// The time between two samples.
+    pub fn period(&self) -> Duration {
+        self.period
+    }
+    pub fn samples(&self) -> &[u32] {
+        &self.samples
+    }
+    pub fn len(&self) -> usize {
+        self.samples.len()
+    }
+    pub fn is_empty(&self) -> bool {
+        self.samples.is_empty()
+    }
+    /// Appends a sample, bit `n` holds the level of channel `n`.
+    pub fn push(&mut self, sample: u32) {
+        self.samples.push(sample);
+    }
+    /// The level of a channel in a sample.
+    pub fn level(&self, sample: usize, channel: usize) -> bool {
+        self.samples[sample] >> channel & 1 == 1
+    }
+    /// Removes the samples taken so far.
+    pub fn clear(&mut self) {
+        self.samples.clear();
+    }
+
+    /// Writes the trace as a Value Change Dump, which GTKWave and PulseView
+    /// can open.
+    pub fn write_vcd(&self, mut out: impl Write) -> io::Result<()> {
+        let ids: Vec<String> = (0..self.channels.len()).map(vcd_identifier).collect();
+        let step = self.period.as_nanos();
+
+        writeln!(out, "$version {} {} $end", env!("CARGO_PKG_NAME"), env!("CARGO_PKG_VERSION"))?;
+        writeln!(out, "$timescale 1ns $end")?;
+        writeln!(out, "$scope module {} $end", self.name)?;
+        for (id, channel) in ids.iter().zip(&self.channels) {
+            writeln!(out, "$var wire 1 {} {} $end", id, channel)?;
+        }
+        writeln!(out, "$upscope $end")?;
+        writeln!(out, "$enddefinitions $end")?;
+
+        let mut previous = None;
+        for (index, &sample) in self.samples.iter().enumerate() {
+            let changed = match previous {
+                Some(previous) => sample ^ previous,
+                None => u32::MAX
+            };
+            if changed & mask(self.channels.len()) == 0 {
+                continue;
+            }
+            writeln!(out, "#{}", index as u128 * step)?;
+            if previous.is_none() {
+                writeln!(out, "$dumpvars")?;
+            }
+            for (channel, id) in ids.iter().enumerate() {
+                if changed >> channel & 1 == 1 {
+                    writeln!(out, "{}{}", sample >> channel & 1, id)?;
+                }
+            }
+            if previous.is_none() {
+                writeln!(out, "$end")?;
+            }
+            previous = Some(sample);
+        }
+        // Mark the end of the capture so the last level has a length.
+        writeln!(out, "#{}", self.samples.len() as u128 * step)
+    }
+    /// Writes the trace to a VCD file.
+    pub fn save_vcd(&self, path: impl AsRef<Path>) -> io::Result<()> {
+        let mut out = BufWriter::new(File::create(path)?);
+        self.write_vcd(&mut out)?;
+        out.flush()
+    }
+}
+
+/// The bits that hold a channel in a sample.
+fn mask(channels: usize) -> u32 {
+    if channels >= 32 { u32::MAX } else { (1 << channels) - 1 }
+}
+
+/// VCD identifiers are made of the printable characters from `!` to `~`.
+fn vcd_identifier(mut index: usize) -> String {
+    let mut id = String::new();
+    loop {
+        id.push((b'!' + (index % 94) as u8) as char);
+        index /= 94;
+        if index == 0 {
+            return id;
+        }
+        index -= 1;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::LogicTrace;
+
+    #[test]
+    pub fn test_vcd() {
+        let mut trace = LogicTrace::new("spi", &["SCLK", "MOSI"], Duration::from_nanos(500));
+        for sample in [0b10, 0b11, 0b11, 0b00] {
+            trace.push(sample);
+        }
+        let mut out = vec![];
+        trace.write_vcd(&mut out).unwrap();
+        let vcd = String::from_utf8(out).unwrap();
+
+        let body = vcd.split("$enddefinitions $end\n").nth(1).unwrap();
+        assert!(vcd.contains("$var wire 1 ! SCLK $end\n$var wire 1 \" MOSI $end"));
+        // Only the changes are dumped, the unchanged third sample is left out.
+        assert_eq!(body, "#0\n$dumpvars\n0!\n1\"\n$end\n#500\n1!\n#1500\n0!\n0\"\n#2000\n");
+    }
+}
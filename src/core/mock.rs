@@ -0,0 +1,51 @@
+use std::{collections::VecDeque, fmt::Display};
+
+/// The ordered expectations of a mock device and what it saw so far.
+pub(crate) struct Expectations<T> {
+    pending: VecDeque<T>,
+    /// Every transaction seen, with the expectation it was checked against
+    /// and if it matched.
+    seen: Vec<(Option<T>, T, bool)>,
+    /// Set once the report was looked at, so dropping the mock stays quiet.
+    pub(crate) checked: bool
+}
+
+impl<T: Clone + Display> Expectations<T> {
+    pub(crate) fn new(expected: impl IntoIterator<Item = T>) -> Self {
+        Self {
+            pending: expected.into_iter().collect(),
+            seen: vec![],
+            checked: false
+        }
+    }
+    /// Checks a transaction against the next expectation and hands back the
+    /// expectation when it matches, so its response can be sent.
+    pub(crate) fn check(&mut self, actual: T, matches: impl Fn(&T, &T) -> bool) -> Option<T> {
+        let expected = self.pending.pop_front();
+        let matched = expected.as_ref().is_some_and(|e| matches(e, &actual));
+        self.seen.push((expected.clone(), actual, matched));
+        expected.filter(|_| matched)
+    }
+    /// A diff of the expected and the actual transactions, if they differ.
+    ///
+    /// Matching transactions are indented, expected ones start with `-` and
+    /// the ones that happened instead start with `+`.
+    pub(crate) fn report(&self) -> Option<String> {
+        if self.pending.is_empty() && self.seen.iter().all(|(_, _, matched)| *matched) {
+            return None;
+        }
+        let mut diff = vec![];
+        for (expected, actual, matched) in &self.seen {
+            if *matched {
+                diff.push(format!("  {}", expected.as_ref().unwrap_or(actual)));
+                continue;
+            }
+            if let Some(expected) = expected {
+                diff.push(format!("- {}", expected));
+            }
+            diff.push(format!("+ {}", actual));
+        }
+        diff.extend(self.pending.iter().map(|missing| format!("- {}", missing)));
+        Some(diff.join("\n"))
+    }
+}
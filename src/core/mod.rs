@@ -1,5 +1,15 @@
+pub mod fault;
+pub mod golden;
+pub mod logic;
+pub(crate) mod mock;
 pub mod register;
+pub mod sigrok;
+pub mod trace;
 pub mod wire;
 
+pub use crate::core::fault::*;
+pub use crate::core::golden::*;
+pub use crate::core::logic::*;
 pub use crate::core::register::*;
-pub use crate::core::wire::*;
\ No newline at end of file
+pub use crate::core::trace::*;
+pub use crate::core::wire::*;
@@ -3,19 +3,29 @@ use super::{byte_to_bits, Port};
 
 pub struct Register {
     buffer: Port,
-    populator: Option<fn() -> Vec<u8>>,
+    populator: Option<Box<dyn FnMut() -> Vec<u8> + Send>>,
     read_only: bool,
     // Allows us to restore the contents of the write register after.
-    backing: Port
+    backing: Port,
+    /// Sees every byte written, along with the bits of the byte in progress.
+    observer: Option<Box<dyn FnMut(u8) + Send>>,
+    incoming: (u8, usize)
 }
 
 impl Register {
     pub fn new_read_only(populator: fn() -> Vec<u8>) -> Self {
+        Self::from_fn(populator)
+    }
+    /// A read only register filled by a closure on every read, e.g. to play
+    /// back a sequence of values.
+    pub fn from_fn(populator: impl FnMut() -> Vec<u8> + Send + 'static) -> Self {
         Self {
             buffer: Port::new(),
-            populator: Some(populator),
+            populator: Some(Box::new(populator)),
             read_only: true,
-            backing: Port::new()
+            backing: Port::new(),
+            observer: None,
+            incoming: (0, 0)
         }
     }
     pub fn new_writeable() -> Self {
@@ -23,18 +33,38 @@ impl Register {
             buffer: Port::new(),
             populator: None,
             read_only: false,
-            backing: Port::new()
+            backing: Port::new(),
+            observer: None,
+            incoming: (0, 0)
         };
         value.backing.write_byte(0x00);
         value.refill_buffers();
         value
     }
+    /// Calls `observer` with every byte written to the register, even when
+    /// the register is read only.
+    pub fn on_write(mut self, observer: impl FnMut(u8) + Send + 'static) -> Self {
+        self.observer = Some(Box::new(observer));
+        self
+    }
     pub fn start_write(&mut self) {
         if self.read_only {
             return; // cannot write on a read-only register.
         }
         self.buffer.clear();
         self.backing.clear();
+        self.incoming = (0, 0);
+    }
+    /// Writes a byte behind the ones written since [Register::start_write],
+    /// so a burst reads back in the order it was written.
+    pub fn append_byte(&mut self, value: u8) {
+        if let Some(observer) = &mut self.observer {
+            observer(value);
+        }
+        if !self.read_only {
+            self.backing.append_byte(value);
+            self.buffer.append_byte(value);
+        }
     }
     pub fn is_done(&self) -> bool {
         self.buffer.bits_read() == 0
@@ -45,6 +75,15 @@ impl Register {
         }
     }
     pub fn write(&mut self, bit: bool) {
+        if let Some(observer) = &mut self.observer {
+            let (byte, bits) = &mut self.incoming;
+            *byte = *byte << 1 | bit as u8;
+            *bits += 1;
+            if *bits == 8 {
+                observer(*byte);
+                self.incoming = (0, 0);
+            }
+        }
         if !self.read_only {
             // Not a read only register. We will write this bit.
             self.backing.write(bit);
@@ -54,7 +93,7 @@ impl Register {
     pub fn start_read(&mut self) {
         if self.read_only {
             // Populate the value.
-            let mut boof = (self.populator.as_ref().unwrap())();
+            let mut boof = (self.populator.as_mut().unwrap())();
             boof.reverse();
             for byte in  boof {
                 self.buffer.write_byte(byte);
@@ -121,6 +160,16 @@ mod tests {
         register.start_read();
         assert_eq!(register.read_byte().unwrap(), 0x21);
         assert_eq!(register.read_byte().unwrap(), 0x22);
+        register.finish_read();
+
+        register.start_write();
+        register.append_byte(0x22);
+        register.append_byte(0x21);
+        register.start_read();
+        assert_eq!(register.read_byte().unwrap(), 0x22);
+        assert_eq!(register.read_byte().unwrap(), 0x21);
+        register.finish_read();
+        assert_eq!(register.read_byte().unwrap(), 0x22);
 
 
     }
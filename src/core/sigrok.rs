@@ -0,0 +1,332 @@
+use std::{fs, io, path::Path, time::Duration};
+
+use super::LogicTrace;
+
+/// The file in the session that holds the samples.
+const CAPTURE_FILE: &str = "logic-1";
+
+/// Reading and writing sigrok session files, the `.sr` captures PulseView and
+/// sigrok-cli work with.
+///
+/// A session is a ZIP archive with a `version` file, an INI style `metadata`
+/// file and the raw samples, `unitsize` bytes per sample with bit `n` holding
+/// channel `n`.
+impl LogicTrace {
+    /// Encodes the trace as a sigrok session, at most 32 channels fit.
+    pub fn to_sigrok(&self) -> Vec<u8> {
+        let unit = self.channels().len().div_ceil(8).max(1);
+        let mut metadata = String::new();
+        // libsigrok only looks at the version file, this line is informative.
+        metadata += "[global]\nsigrok version=0.5.2\n\n[device 1]\n";
+        metadata += &format!("capturefile={}\n", CAPTURE_FILE);
+        metadata += &format!("total probes={}\n", self.channels().len());
+        metadata += &format!("samplerate={}\n", format_samplerate(self.period()));
+        metadata += "total analog=0\n";
+        for (index, channel) in self.channels().iter().enumerate() {
+            metadata += &format!("probe{}={}\n", index + 1, channel);
+        }
+        metadata += &format!("unitsize={}\n", unit);
+
+        let data: Vec<u8> = self.samples().iter()
+            .flat_map(|sample| sample.to_le_bytes().into_iter().take(unit))
+            .collect();
+        let mut archive = ZipWriter::default();
+        archive.add("version", b"2");
+        archive.add("metadata", metadata.as_bytes());
+        archive.add(&format!("{}-1", CAPTURE_FILE), &data);
+        archive.finish()
+    }
+    /// Decodes the first logic device of a sigrok session, the trace is named `sigrok`.
+    pub fn from_sigrok(bytes: &[u8]) -> io::Result<Self> {
+        let files = read_zip(bytes)?;
+        let file = |name: &str| files.iter().find(|(n, _)| n == name).map(|(_, data)| data);
+
+        let version = file("version").ok_or_else(|| invalid("the session has no version"))?;
+        if String::from_utf8_lossy(version).trim() != "2" {
+            return Err(invalid("only version 2 sessions are supported"));
+        }
+        let metadata = file("metadata").ok_or_else(|| invalid("the session has no metadata"))?;
+        let metadata = String::from_utf8_lossy(metadata);
+
+        let mut section = "";
+        let mut capture = None;
+        let mut samplerate = None;
+        let mut unit = 1;
+        let mut probes = vec![];
+        for line in metadata.lines().map(str::trim) {
+            if let Some(name) = line.strip_prefix('[').and_then(|l| l.strip_suffix(']')) {
+                section = name;
+                continue;
+            }
+            // Only the first logic device is imported.
+            let Some((key, value)) = line.split_once('=') else {
+                continue;
+            };
+            if section != "device 1" {
+                continue;
+            }
+            match key {
+                "capturefile" => capture = Some(value.to_string()),
+                "samplerate" => samplerate = Some(parse_samplerate(value)?),
+                "unitsize" => unit = value.parse().map_err(|_| invalid("bad unitsize"))?,
+                _ => if let Some(index) = key.strip_prefix("probe").and_then(|i| i.parse::<usize>().ok()) {
+                    probes.push((index, value.to_string()));
+                }
+            }
+        }
+        let capture = capture.ok_or_else(|| invalid("the session has no logic capture"))?;
+        if !(1..=4).contains(&unit) {
+            return Err(invalid("at most 32 channels are supported"));
+        }
+        if probes.len() > 32 {
+            return Err(invalid("at most 32 channels are supported"));
+        }
+        probes.sort();
+        let channels: Vec<&str> = probes.iter().map(|(_, name)| name.as_str()).collect();
+        // A rate of zero has no period and one beyond 1 GHz rounds down to none.
+        let period = Duration::try_from_secs_f64(1.0 / samplerate.unwrap_or(1.0))
+            .ok()
+            .filter(|period| !period.is_zero())
+            .ok_or_else(|| invalid("bad samplerate"))?;
+
+        // The samples are either in a single file or split into numbered chunks.
+        let mut chunks: Vec<(usize, &Vec<u8>)> = files.iter()
+            .filter_map(|(name, data)| {
+                let rest = name.strip_prefix(capture.as_str())?;
+                if rest.is_empty() {
+                    Some((0, data))
+                } else {
+                    Some((rest.strip_prefix('-')?.parse().ok()?, data))
+                }
+            })
+            .collect();
+        chunks.sort_by_key(|(index, _)| *index);
+
+        let mut trace = LogicTrace::new("sigrok", &channels, period);
+        for (_, data) in chunks {
+            for sample in data.chunks_exact(unit) {
+                let mut word = [0; 4];
+                word[..unit].copy_from_slice(sample);
+                trace.push(u32::from_le_bytes(word));
+            }
+        }
+        Ok(trace)
+    }
+    /// Writes the trace to a sigrok session file.
+    pub fn save_sigrok(&self, path: impl AsRef<Path>) -> io::Result<()> {
+        fs::write(path, self.to_sigrok())
+    }
+    /// Reads a trace from a sigrok session file.
+    pub fn load_sigrok(path: impl AsRef<Path>) -> io::Result<Self> {
+        Self::from_sigrok(&fs::read(path)?)
+    }
+}
+
+fn invalid(message: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message.to_string())
+}
+
+/// Parses a rate like `400 kHz` or `1MHz` into Hz.
+fn parse_samplerate(value: &str) -> io::Result<f64> {
+    let value = value.trim();
+    let split = value.find(|c: char| !c.is_ascii_digit() && c != '.').unwrap_or(value.len());
+    let (number, unit) = value.split_at(split);
+    let number: f64 = number.parse().map_err(|_| invalid("bad samplerate"))?;
+    let scale = match unit.trim() {
+        "" | "Hz" => 1.0,
+        "kHz" => 1e3,
+        "MHz" => 1e6,
+        "GHz" => 1e9,
+        _ => return Err(invalid("bad samplerate"))
+    };
+    Ok(number * scale)
+}
+
+/// Writes a sample period as a rate in the largest unit that keeps it whole.
+///
+/// Sessions only hold whole hertz, so traces sampled slower than that are
+/// written at 1 Hz.
+fn format_samplerate(period: Duration) -> String {
+    let hz = ((1e9 / period.as_nanos().max(1) as f64).round() as u64).max(1);
+    for (scale, unit) in [(1_000_000_000, "GHz"), (1_000_000, "MHz"), (1_000, "kHz")] {
+        if hz >= scale && hz.is_multiple_of(scale) {
+            return format!("{} {}", hz / scale, unit);
+        }
+    }
+    format!("{} Hz", hz)
+}
+
+const LOCAL_HEADER: u32 = 0x04034b50;
+const CENTRAL_HEADER: u32 = 0x02014b50;
+const END_OF_DIRECTORY: u32 = 0x06054b50;
+const STORED: u16 = 0;
+const DEFLATED: u16 = 8;
+
+/// Just enough of a ZIP writer for a session, every file is deflated.
+#[derive(Default)]
+struct ZipWriter {
+    out: Vec<u8>,
+    directory: Vec<u8>,
+    files: u16
+}
+
+impl ZipWriter {
+    fn add(&mut self, name: &str, data: &[u8]) {
+        let compressed = miniz_oxide::deflate::compress_to_vec(data, 6);
+        let offset = self.out.len() as u32;
+        let crc = crc32(data);
+        // Version needed, flags, method, time and date, CRC and sizes.
+        let mut common = vec![];
+        common.extend(20u16.to_le_bytes());
+        common.extend(0u16.to_le_bytes());
+        common.extend(DEFLATED.to_le_bytes());
+        common.extend(0u32.to_le_bytes());
+        common.extend(crc.to_le_bytes());
+        common.extend((compressed.len() as u32).to_le_bytes());
+        common.extend((data.len() as u32).to_le_bytes());
+        common.extend((name.len() as u16).to_le_bytes());
+
+        self.out.extend(LOCAL_HEADER.to_le_bytes());
+        self.out.extend(&common);
+        self.out.extend(0u16.to_le_bytes());
+        self.out.extend(name.as_bytes());
+        self.out.extend(&compressed);
+
+        self.directory.extend(CENTRAL_HEADER.to_le_bytes());
+        self.directory.extend(20u16.to_le_bytes());
+        self.directory.extend(&common);
+        // Extra field, comment, disk and attributes.
+        self.directory.extend([0; 12]);
+        self.directory.extend(offset.to_le_bytes());
+        self.directory.extend(name.as_bytes());
+        self.files += 1;
+    }
+    fn finish(mut self) -> Vec<u8> {
+        let offset = self.out.len() as u32;
+        self.out.extend(&self.directory);
+        self.out.extend(END_OF_DIRECTORY.to_le_bytes());
+        self.out.extend([0; 4]);
+        self.out.extend(self.files.to_le_bytes());
+        self.out.extend(self.files.to_le_bytes());
+        self.out.extend((self.directory.len() as u32).to_le_bytes());
+        self.out.extend(offset.to_le_bytes());
+        self.out.extend(0u16.to_le_bytes());
+        self.out
+    }
+}
+
+/// Reads every stored or deflated file in a ZIP archive.
+fn read_zip(bytes: &[u8]) -> io::Result<Vec<(String, Vec<u8>)>> {
+    let u16_at = |at: usize| bytes.get(at..at + 2).map(|b| u16::from_le_bytes([b[0], b[1]]))
+        .ok_or_else(|| invalid("truncated archive"));
+    let u32_at = |at: usize| bytes.get(at..at + 4).map(|b| u32::from_le_bytes([b[0], b[1], b[2], b[3]]))
+        .ok_or_else(|| invalid("truncated archive"));
+
+    // The end of directory record is followed by a comment of up to 64 KiB.
+    let end = (0..bytes.len().saturating_sub(21)).rev()
+        .take(u16::MAX as usize + 1)
+        .find(|&at| u32_at(at).is_ok_and(|sig| sig == END_OF_DIRECTORY))
+        .ok_or_else(|| invalid("not a ZIP archive"))?;
+    let count = u16_at(end + 10)? as usize;
+    let mut at = u32_at(end + 16)? as usize;
+
+    let mut files = vec![];
+    for _ in 0..count {
+        if u32_at(at)? != CENTRAL_HEADER {
+            return Err(invalid("broken central directory"));
+        }
+        let method = u16_at(at + 10)?;
+        let crc = u32_at(at + 16)?;
+        let compressed = u32_at(at + 20)? as usize;
+        let name_length = u16_at(at + 28)? as usize;
+        let extra_length = u16_at(at + 30)? as usize;
+        let comment_length = u16_at(at + 32)? as usize;
+        let local = u32_at(at + 42)? as usize;
+        let name = bytes.get(at + 46..at + 46 + name_length).ok_or_else(|| invalid("truncated archive"))?;
+        let name = String::from_utf8_lossy(name).into_owned();
+        at += 46 + name_length + extra_length + comment_length;
+
+        if u32_at(local)? != LOCAL_HEADER {
+            return Err(invalid("broken local header"));
+        }
+        let start = local + 30 + u16_at(local + 26)? as usize + u16_at(local + 28)? as usize;
+        let raw = bytes.get(start..start + compressed).ok_or_else(|| invalid("truncated archive"))?;
+        let data = match method {
+            STORED => raw.to_vec(),
+            DEFLATED => miniz_oxide::inflate::decompress_to_vec(raw)
+                .map_err(|_| invalid("broken deflate stream"))?,
+            _ => return Err(invalid("unsupported compression method"))
+        };
+        if crc32(&data) != crc {
+            return Err(invalid("checksum mismatch"));
+        }
+        files.push((name, data));
+    }
+    Ok(files)
+}
+
+/// The CRC-32 used by ZIP archives.
+fn crc32(data: &[u8]) -> u32 {
+    let mut crc = !0u32;
+    for &byte in data {
+        crc ^= byte as u32;
+        for _ in 0..8 {
+            crc = if crc & 1 == 1 { crc >> 1 ^ 0xEDB88320 } else { crc >> 1 };
+        }
+    }
+    !crc
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use crate::core::LogicTrace;
+
+    use super::{crc32, format_samplerate, parse_samplerate, ZipWriter};
+
+    #[test]
+    pub fn test_sigrok_round_trip() {
+        let channels: Vec<String> = (0..10).map(|i| format!("D{}", i)).collect();
+        let channels: Vec<&str> = channels.iter().map(String::as_str).collect();
+        let mut trace = LogicTrace::new("spi", &channels, Duration::from_nanos(250));
+        for sample in [0x000, 0x3FF, 0x201, 0x0F0] {
+            trace.push(sample);
+        }
+
+        let session = LogicTrace::from_sigrok(&trace.to_sigrok()).unwrap();
+        assert_eq!(session.channels(), trace.channels());
+        assert_eq!(session.period(), trace.period());
+        assert_eq!(session.samples(), trace.samples());
+    }
+
+    #[test]
+    pub fn test_bad_sessions() {
+        let session = |metadata: &str| {
+            let mut archive = ZipWriter::default();
+            archive.add("version", b"2");
+            archive.add("metadata", format!("[device 1]\ncapturefile=logic-1\n{}", metadata).as_bytes());
+            archive.add("logic-1-1", &[0x01, 0x00]);
+            LogicTrace::from_sigrok(&archive.finish()).map_err(|err| err.to_string())
+        };
+        assert_eq!(session("samplerate=0 Hz\nprobe1=D0\n").unwrap_err(), "bad samplerate");
+        assert_eq!(session("samplerate=5 GHz\nprobe1=D0\n").unwrap_err(), "bad samplerate");
+        let probes: String = (1..=33).map(|i| format!("probe{}=D{}\n", i, i)).collect();
+        assert_eq!(session(&probes).unwrap_err(), "at most 32 channels are supported");
+
+        // A slow trace still loads, at the slowest rate a session holds.
+        let mut trace = LogicTrace::new("slow", &["D0"], Duration::from_secs(3));
+        trace.push(1);
+        assert_eq!(LogicTrace::from_sigrok(&trace.to_sigrok()).unwrap().period(), Duration::from_secs(1));
+    }
+
+    #[test]
+    pub fn test_samplerate() {
+        assert_eq!(parse_samplerate("400 kHz").unwrap(), 400e3);
+        assert_eq!(parse_samplerate("24MHz").unwrap(), 24e6);
+        assert_eq!(format_samplerate(Duration::from_nanos(250)), "4 MHz");
+        assert_eq!(format_samplerate(Duration::from_micros(3)), "333333 Hz");
+        assert_eq!(format_samplerate(Duration::from_secs(3)), "1 Hz");
+        assert_eq!(crc32(b"123456789"), 0xCBF43926);
+    }
+}
@@ -0,0 +1,287 @@
+use std::{collections::HashMap, fmt::{Debug, Display}, sync::{Arc, Mutex, RwLock}};
+
+/// How much a device has to say, from nothing at all up to every bit.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash, Default)]
+pub enum Verbosity {
+    Off,
+    Error,
+    Warn,
+    #[default]
+    Info,
+    Debug,
+    Trace
+}
+
+/// Which way a value travelled.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Direction {
+    MasterToSlave,
+    SlaveToMaster
+}
+
+/// The device an event came from.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum DeviceId {
+    /// An I2C device by its 7-bit address.
+    I2c(u8),
+    /// A SPI device by the number it was given when attaching the tracer.
+    Spi(u32)
+}
+
+impl Display for DeviceId {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            DeviceId::I2c(address) => write!(f, "i2c {:#x}", address),
+            DeviceId::Spi(id) => write!(f, "spi {}", id)
+        }
+    }
+}
+
+/// Something a device did, with the details a test may want to look at.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct TraceEvent {
+    /// The device the event came from, set by the [DeviceTracer] emitting it.
+    pub device: Option<DeviceId>,
+    pub verbosity: Verbosity,
+    pub message: String,
+    /// The states the device moved between.
+    pub transition: Option<(String, String)>,
+    /// The register involved.
+    pub register: Option<u32>,
+    /// The byte or word involved and which way it went.
+    pub value: Option<(u32, Direction)>
+}
+
+impl TraceEvent {
+    /// Creates an event, the device and verbosity are filled in by the [DeviceTracer].
+    pub fn new(message: impl Into<String>) -> Self {
+        Self {
+            device: None,
+            verbosity: Verbosity::Info,
+            message: message.into(),
+            transition: None,
+            register: None,
+            value: None
+        }
+    }
+    pub fn transition(mut self, from: impl Debug, to: impl Debug) -> Self {
+        self.transition = Some((format!("{:?}", from), format!("{:?}", to)));
+        self
+    }
+    pub fn register(mut self, register: u32) -> Self {
+        self.register = Some(register);
+        self
+    }
+    pub fn value(mut self, value: u32, direction: Direction) -> Self {
+        self.value = Some((value, direction));
+        self
+    }
+}
+
+impl Display for TraceEvent {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self.device {
+            Some(device) => write!(f, "[{}] {}", device, self.message),
+            None => write!(f, "{}", self.message)
+        }
+    }
+}
+
+/// Where trace events end up.
+pub trait TraceSink: Send + Sync {
+    fn record(&self, event: &TraceEvent);
+}
+
+/// Any closure can be a sink, which makes it easy to forward events to a
+/// logging framework.
+impl<F: Fn(&TraceEvent) + Send + Sync> TraceSink for F {
+    fn record(&self, event: &TraceEvent) {
+        self(event);
+    }
+}
+
+/// Prints every event to stdout.
+pub struct StdoutSink;
+
+impl TraceSink for StdoutSink {
+    fn record(&self, event: &TraceEvent) {
+        println!("{}", event);
+    }
+}
+
+/// Keeps every event in memory so tests can look at them.
+#[derive(Clone, Default)]
+pub struct MemorySink {
+    events: Arc<Mutex<Vec<TraceEvent>>>
+}
+
+impl MemorySink {
+    pub fn new() -> Self {
+        Self::default()
+    }
+    /// Every event recorded so far.
+    pub fn events(&self) -> Vec<TraceEvent> {
+        self.events.lock().unwrap().clone()
+    }
+    /// Takes the events recorded so far.
+    pub fn take(&self) -> Vec<TraceEvent> {
+        std::mem::take(&mut *self.events.lock().unwrap())
+    }
+}
+
+impl TraceSink for MemorySink {
+    fn record(&self, event: &TraceEvent) {
+        self.events.lock().unwrap().push(event.clone());
+    }
+}
+
+/// Filters events by verbosity and hands them to a [TraceSink].
+///
+/// Clones share the sink and the verbosity settings. The default tracer
+/// drops everything.
+#[derive(Clone, Default)]
+pub struct Tracer {
+    inner: Option<Arc<TracerInner>>
+}
+
+struct TracerInner {
+    sink: Box<dyn TraceSink>,
+    verbosity: RwLock<Verbosity>,
+    /// Verbosity overrides for single devices.
+    devices: RwLock<HashMap<DeviceId, Verbosity>>
+}
+
+impl Tracer {
+    /// Creates a tracer that passes events up to [Verbosity::Info] on to the sink.
+    pub fn new(sink: impl TraceSink + 'static) -> Self {
+        Self {
+            inner: Some(Arc::new(TracerInner {
+                sink: Box::new(sink),
+                verbosity: RwLock::new(Verbosity::default()),
+                devices: RwLock::default()
+            }))
+        }
+    }
+    /// A tracer that drops everything.
+    pub fn silent() -> Self {
+        Self::default()
+    }
+    /// A tracer that prints to stdout.
+    pub fn stdout() -> Self {
+        Self::new(StdoutSink)
+    }
+    /// Sets the verbosity of every device without an override.
+    pub fn set_verbosity(&self, verbosity: Verbosity) {
+        if let Some(inner) = &self.inner {
+            *inner.verbosity.write().unwrap() = verbosity;
+        }
+    }
+    /// Overrides the verbosity of a single device.
+    pub fn set_device_verbosity(&self, device: DeviceId, verbosity: Verbosity) {
+        if let Some(inner) = &self.inner {
+            inner.devices.write().unwrap().insert(device, verbosity);
+        }
+    }
+    /// Checks if a device would get an event through at the given verbosity.
+    pub fn enabled(&self, device: DeviceId, verbosity: Verbosity) -> bool {
+        let Some(inner) = &self.inner else {
+            return false;
+        };
+        let limit = inner.devices.read().unwrap().get(&device).copied()
+            .unwrap_or_else(|| *inner.verbosity.read().unwrap());
+        verbosity != Verbosity::Off && verbosity <= limit
+    }
+    /// Hands an event to the sink if its verbosity is enabled, events
+    /// without a device go by the verbosity set for every device.
+    pub fn emit(&self, event: TraceEvent) {
+        let enabled = match event.device {
+            Some(device) => self.enabled(device, event.verbosity),
+            None => self.inner.as_ref().is_some_and(|inner| {
+                event.verbosity != Verbosity::Off && event.verbosity <= *inner.verbosity.read().unwrap()
+            })
+        };
+        if enabled
+            && let Some(inner) = &self.inner
+        {
+            inner.sink.record(&event);
+        }
+    }
+    /// A handle for a single device to emit events through.
+    pub fn device(&self, device: DeviceId) -> DeviceTracer {
+        DeviceTracer { tracer: self.clone(), device }
+    }
+}
+
+/// A [Tracer] bound to a single device.
+#[derive(Clone)]
+pub struct DeviceTracer {
+    tracer: Tracer,
+    device: DeviceId
+}
+
+impl DeviceTracer {
+    /// Checks if an event at the given verbosity would get through, so
+    /// callers can skip building its message.
+    pub fn enabled(&self, verbosity: Verbosity) -> bool {
+        self.tracer.enabled(self.device, verbosity)
+    }
+    /// Emits an event at the given verbosity.
+    pub fn log(&self, verbosity: Verbosity, mut event: TraceEvent) {
+        event.device = Some(self.device);
+        event.verbosity = verbosity;
+        self.tracer.emit(event);
+    }
+    pub fn error(&self, event: TraceEvent) {
+        self.log(Verbosity::Error, event);
+    }
+    pub fn warn(&self, event: TraceEvent) {
+        self.log(Verbosity::Warn, event);
+    }
+    pub fn info(&self, event: TraceEvent) {
+        self.log(Verbosity::Info, event);
+    }
+    pub fn debug(&self, event: TraceEvent) {
+        self.log(Verbosity::Debug, event);
+    }
+    pub fn trace(&self, event: TraceEvent) {
+        self.log(Verbosity::Trace, event);
+    }
+}
+
+impl Default for DeviceTracer {
+    fn default() -> Self {
+        Tracer::silent().device(DeviceId::Spi(0))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{DeviceId, MemorySink, TraceEvent, Tracer, Verbosity};
+
+    #[test]
+    pub fn test_device_verbosity() {
+        let sink = MemorySink::new();
+        let tracer = Tracer::new(sink.clone());
+        tracer.set_device_verbosity(DeviceId::I2c(0x68), Verbosity::Debug);
+        tracer.set_device_verbosity(DeviceId::I2c(0x32), Verbosity::Off);
+
+        for address in [0x68, 0x32, 0x10] {
+            let device = tracer.device(DeviceId::I2c(address));
+            device.debug(TraceEvent::new("debug"));
+            device.warn(TraceEvent::new("warn").register(0x12));
+        }
+
+        let events: Vec<_> = sink.take().into_iter().map(|e| (e.device, e.message)).collect();
+        assert_eq!(events, vec![
+            (Some(DeviceId::I2c(0x68)), "debug".to_string()),
+            (Some(DeviceId::I2c(0x68)), "warn".to_string()),
+            (Some(DeviceId::I2c(0x10)), "warn".to_string())
+        ]);
+
+        let device = tracer.device(DeviceId::I2c(0x68));
+        assert!(device.enabled(Verbosity::Debug) && !device.enabled(Verbosity::Trace));
+
+        // The default tracer drops everything.
+        assert!(!Tracer::default().enabled(DeviceId::Spi(0), Verbosity::Error));
+    }
+}
@@ -36,6 +36,30 @@ impl Port {
             self.write(bit);
         }
     }
+    /// Writes a byte that [Port::read_byte] hands out after every byte
+    /// already in the port, unlike [Port::write_byte] which puts it first.
+    pub fn append_byte(&mut self, b: u8) {
+        for i in 0..8 {
+            self.buffer.push(b >> i & 1 == 1);
+        }
+    }
+    /// Writes the lowest `bits` bits of a word, MSB first.
+    pub fn write_word(&mut self, value: u32, bits: usize) {
+        for i in (0..bits).rev() {
+            self.write(value >> i & 1 == 1);
+        }
+    }
+    /// Reads `bits` bits in the order they were written, MSB first.
+    pub fn read_word(&mut self, bits: usize) -> Option<u32> {
+        if self.buffer.len() < bits {
+            return None;
+        }
+        let mut value = 0;
+        for _ in 0..bits {
+            value = (value << 1) | self.read().unwrap() as u32;
+        }
+        Some(value)
+    }
     pub fn read(&mut self) -> Option<bool> {
         self.buffer.pop()
     }
@@ -75,6 +99,11 @@ mod tests {
         }
 
         assert_eq!(port.read_byte().unwrap(), 0b11010001);
+
+        port.write_byte(0x21);
+        port.append_byte(0x22);
+        assert_eq!(port.read_byte().unwrap(), 0x21);
+        assert_eq!(port.read_byte().unwrap(), 0x22);
         
     }
 }
\ No newline at end of file
@@ -1,4 +1,6 @@
-use super::I2CSlave;
+use crate::core::{FaultPlan, Tracer};
+
+use super::{I2CSlave, I2cEvent, I2cFault, I2cProbe};
 
 
 
@@ -12,7 +14,19 @@ pub enum LineCondition {
 pub struct I2CBus {
     devices: Vec<I2CSlave>,
     line: Option<u8>,
-    line_bit: Option<bool>
+    line_bit: Option<bool>,
+    /// Handed to every device on the bus.
+    tracer: Tracer,
+    /// Everything watching the bus.
+    probes: Vec<Box<dyn I2cProbe>>,
+    /// The SCL cycles that went by so far.
+    cycle: u64,
+    /// The faults to inject, if any.
+    faults: Option<FaultPlan<I2cFault>>,
+    /// The bytes that went by since the faults were set.
+    bytes: u64,
+    /// If SDA is held low for the acknowledge of the byte just read.
+    held: bool
 }
 
 impl I2CBus {
@@ -20,22 +34,109 @@ impl I2CBus {
         Self {
             devices: vec![],
             line: None,
-            line_bit: None
+            line_bit: None,
+            tracer: Tracer::silent(),
+            probes: vec![],
+            cycle: 0,
+            faults: None,
+            bytes: 0,
+            held: false
+        }
+    }
+    /// Attaches a probe, it sees every [I2cEvent] from now on.
+    pub fn add_probe(&mut self, probe: impl I2cProbe + 'static) {
+        self.probes.push(Box::new(probe));
+    }
+    /// The SCL cycles that went by so far.
+    pub fn cycles(&self) -> u64 {
+        self.cycle
+    }
+    /// Injects faults into the traffic from now on, see [I2cFault].
+    pub fn set_faults(&mut self, faults: FaultPlan<I2cFault>) {
+        self.faults = Some(faults);
+        self.bytes = 0;
+    }
+    /// The faults injected, if any were set.
+    pub fn faults(&self) -> Option<&FaultPlan<I2cFault>> {
+        self.faults.as_ref()
+    }
+    /// Rolls the faults for the next byte, taking devices that vanish off the bus.
+    fn next_faults(&mut self) -> Vec<I2cFault> {
+        let Some(plan) = &mut self.faults else {
+            return vec![];
+        };
+        let faults = plan.roll(self.bytes);
+        self.bytes += 1;
+        for fault in &faults {
+            if let I2cFault::Vanish(address) = fault {
+                self.devices.retain(|d| d.address() != *address);
+            }
+        }
+        faults
+    }
+    fn emit(&mut self, event: I2cEvent) {
+        for probe in &mut self.probes {
+            probe.on_event(&event, self.cycle);
         }
+        self.cycle += event.cycles();
     }
-    pub fn add_device(&mut self, device: I2CSlave) {
+    pub fn add_device(&mut self, mut device: I2CSlave) {
+        device.set_tracer(&self.tracer);
         self.devices.push(device);
     }
+    /// Reports what every device on the bus is doing to the given [Tracer].
+    pub fn set_tracer(&mut self, tracer: Tracer) {
+        for device in &mut self.devices {
+            device.set_tracer(&tracer);
+        }
+        self.tracer = tracer;
+    }
     pub fn write_byte(&mut self, value: u8, condition: LineCondition) {
+        let ack = self.transmit(value, condition);
+        assert!(ack); // get the ack
+    }
+    /// Writes a byte and returns if a device acknowledged it.
+    pub fn transmit(&mut self, value: u8, condition: LineCondition) -> bool {
+        let faults = self.next_faults();
+        let value = I2cFault::corrupt(value, &faults);
+        if condition == LineCondition::Start {
+            self.emit(I2cEvent::Start);
+        }
+        self.emit(I2cEvent::Byte { value, from_master: true });
         for device in &mut self.devices {
             device.write_byte(value, condition);
         }
-        assert_eq!(self.read_bit(), Some(false)); // get the ack
+        let mut ack = self.read_bit() == Some(false);
+        if faults.contains(&I2cFault::Nack) {
+            ack = false;
+        }
+        if faults.contains(&I2cFault::HoldSdaLow) {
+            ack = true;
+        }
+        self.emit(I2cEvent::Ack { ack, from_master: false });
+        if condition == LineCondition::Stop {
+            self.stop();
+        }
+        ack
     }
+    /// Writes the acknowledge bit after a byte read from a slave.
     pub fn write_bit(&mut self, bit: bool, condition: LineCondition) {
+        let bit = bit && !std::mem::take(&mut self.held);
+        self.emit(I2cEvent::Ack { ack: !bit, from_master: true });
         for device in &mut self.devices {
             device.write_bit(bit, condition);
         }
+        if condition == LineCondition::Stop {
+            self.stop();
+        }
+    }
+    /// Ends the transaction with a stop condition and puts every device back
+    /// to idle. Drivers use this to get the bus back after a failed transfer.
+    pub fn stop(&mut self) {
+        self.emit(I2cEvent::Stop);
+        for device in &mut self.devices {
+            device.reset();
+        }
     }
     pub fn read_bit(&mut self) -> Option<bool> {
         // println!("ENTERING CALL");
@@ -57,6 +158,8 @@ impl I2CBus {
 
     }
     pub fn read_byte(&mut self) -> Option<u8> {
+        let faults = self.next_faults();
+        self.held = faults.contains(&I2cFault::HoldSdaLow);
         for value in &mut self.devices {
             let read = value.read_byte();
             if read.is_some() && self.line.is_some() {
@@ -68,7 +171,13 @@ impl I2CBus {
             }
 
         }
-        self.line.take()
+        let value = self.line.take()
+            .or(self.held.then_some(0))
+            .map(|value| I2cFault::corrupt(value, &faults));
+        if let Some(value) = value {
+            self.emit(I2cEvent::Byte { value, from_master: false });
+        }
+        value
 
     }
 }
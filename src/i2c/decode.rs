@@ -0,0 +1,155 @@
+use crate::core::LogicTrace;
+
+use super::{I2cEvent, I2cProbe, I2cRecord, I2cRecorder, I2cWaveform};
+
+/// Something on the lines that does not make up a valid I2C frame.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum I2cFrameError {
+    /// A start or stop condition came in the middle of a byte, after `bits`
+    /// of its nine bits.
+    Truncated { at: usize, bits: usize },
+    /// SCL was clocked while no transaction was going on.
+    ClockWhileIdle { at: usize },
+    /// The trace ended in the middle of a transaction.
+    Unterminated { at: usize }
+}
+
+/// What [I2cDecoder::decode] made of a trace: the transactions from start to
+/// stop and the framing errors found on the way.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct I2cDecoded {
+    /// The transactions, `start` and `end` are sample indices of the trace.
+    pub records: Vec<I2cRecord>,
+    pub errors: Vec<I2cFrameError>
+}
+
+/// Rebuilds I2C transactions from the SCL and SDA levels of a [LogicTrace],
+/// without going through the bus model at all.
+#[derive(Clone, Copy, Debug)]
+pub struct I2cDecoder {
+    /// The channel of SCL.
+    scl: usize,
+    /// The channel of SDA.
+    sda: usize
+}
+
+impl I2cDecoder {
+    /// Creates a decoder reading SCL and SDA from the given channels.
+    pub fn new(scl: usize, sda: usize) -> Self {
+        Self { scl, sda }
+    }
+    pub fn decode(&self, trace: &LogicTrace) -> I2cDecoded {
+        let mut recorder = I2cRecorder::new();
+        let mut errors = vec![];
+        let mut active = false;
+        let mut idle_clock = false;
+        // The bits of the byte and acknowledge being clocked.
+        let mut bits: Vec<bool> = vec![];
+        let mut expecting_address = false;
+        let mut reading = false;
+
+        for at in 1..trace.len() {
+            let scl = (trace.level(at - 1, self.scl), trace.level(at, self.scl));
+            let sda = (trace.level(at - 1, self.sda), trace.level(at, self.sda));
+
+            if scl == (true, true) && sda.0 != sda.1 {
+                // SCL rises once more right before a condition.
+                if active && bits.len() > 1 {
+                    errors.push(I2cFrameError::Truncated { at, bits: bits.len() - 1 });
+                }
+                bits.clear();
+                if !sda.1 {
+                    active = true;
+                    idle_clock = false;
+                    expecting_address = true;
+                    recorder.on_event(&I2cEvent::Start, at as u64);
+                } else if active {
+                    active = false;
+                    recorder.on_event(&I2cEvent::Stop, at as u64);
+                }
+            } else if scl == (false, true) {
+                if !active {
+                    if !idle_clock {
+                        errors.push(I2cFrameError::ClockWhileIdle { at });
+                    }
+                    idle_clock = true;
+                    continue;
+                }
+                bits.push(sda.1);
+                if bits.len() == 9 {
+                    let value = bits[..8].iter().fold(0u8, |byte, &bit| byte << 1 | bit as u8);
+                    let from_master = if expecting_address {
+                        expecting_address = false;
+                        reading = value & 0x01 != 0;
+                        true
+                    } else {
+                        !reading
+                    };
+                    recorder.on_event(&I2cEvent::Byte { value, from_master }, at as u64);
+                    recorder.on_event(&I2cEvent::Ack { ack: !bits[8], from_master: !from_master }, at as u64);
+                    bits.clear();
+                }
+            }
+        }
+        if active {
+            errors.push(I2cFrameError::Unterminated { at: trace.len() });
+        }
+        I2cDecoded { records: recorder.take(), errors }
+    }
+}
+
+/// Reads the channels an [I2cWaveform] writes.
+impl Default for I2cDecoder {
+    fn default() -> Self {
+        Self::new(I2cWaveform::SCL, I2cWaveform::SDA)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::{Arc, Mutex};
+
+    use crate::{i2c::{I2cEvent, I2cProbe, I2cWaveform}, testing};
+
+    use super::{I2cDecoder, I2cFrameError};
+
+    #[test]
+    pub fn test_decode_waveform() {
+        let (mut master, recorder) = testing::i2c_bmi270_recorded();
+        let waveform = Arc::new(Mutex::new(I2cWaveform::default()));
+        master.add_probe(waveform.clone());
+
+        master.write_block(0x68, 0x7D, vec![ 0x02, 0x0E ]);
+        master.read_block(0x68, 0x00, 1);
+
+        let decoded = I2cDecoder::default().decode(waveform.lock().unwrap().trace());
+        assert!(decoded.errors.is_empty());
+        // The lines tell the same story as the bus.
+        let recorded = recorder.lock().unwrap().take();
+        let segments = |records: &[crate::i2c::I2cRecord]| records.iter().map(|r| r.segments.clone()).collect::<Vec<_>>();
+        assert_eq!(segments(&decoded.records), segments(&recorded));
+    }
+
+    #[test]
+    pub fn test_malformed_frames() {
+        let mut waveform = I2cWaveform::default();
+        waveform.on_event(&I2cEvent::Start, 0);
+        waveform.on_event(&I2cEvent::Byte { value: 0xD0, from_master: true }, 1);
+        waveform.on_event(&I2cEvent::Ack { ack: true, from_master: false }, 9);
+        waveform.on_event(&I2cEvent::Stop, 10);
+        let mut trace = waveform.take();
+        // Cut the address short and clock once more after the stop.
+        let samples = trace.samples().to_vec();
+        trace.clear();
+        for sample in samples[..4 * 5].iter().chain(&samples[4 * 10..]).chain(&[0b10, 0b11]) {
+            trace.push(*sample);
+        }
+
+        let decoded = I2cDecoder::default().decode(&trace);
+        assert_eq!(decoded.errors, vec![
+            I2cFrameError::Truncated { at: 4 * 6 - 1, bits: 4 },
+            I2cFrameError::ClockWhileIdle { at: 4 * 6 + 1 }
+        ]);
+        assert_eq!(decoded.records.len(), 1);
+    }
+}
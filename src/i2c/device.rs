@@ -1,5 +1,5 @@
 use std::collections::HashMap;
-use crate::core::{byte_to_bits, Port, Register};
+use crate::core::{byte_to_bits, DeviceId, DeviceTracer, Direction, Port, Register, TraceEvent, Tracer, Verbosity};
 use super::LineCondition;
 
 
@@ -23,7 +23,11 @@ pub struct I2CSlave {
     input_buffer: Port,
     reg_select: Option<u8>,
     /// This will be set to 
-    disengaged: bool
+    disengaged: bool,
+    /// Where the device reports what it is doing.
+    trace: DeviceTracer,
+    /// Called on every stop condition.
+    on_stop: Option<Box<dyn FnMut() + Send>>
 }
 
 
@@ -40,12 +44,26 @@ impl I2CSlave {
             output: Port::new(),
             input_buffer: Port::new(),
             reg_select: None,
-            disengaged: false
+            disengaged: false,
+            trace: Tracer::silent().device(DeviceId::I2c(address)),
+            on_stop: None
         }
     }
+    pub fn address(&self) -> u8 {
+        self.address
+    }
+    /// Reports what the device is doing to the given [Tracer].
+    pub fn set_tracer(&mut self, tracer: &Tracer) {
+        self.trace = tracer.device(DeviceId::I2c(self.address));
+    }
     pub fn create_register(&mut self, address: u8, register: Register) {
         self.registers.insert(address, register);
     }
+    /// Calls `hook` on every stop condition, which is where a transaction
+    /// ends even when the registers can not tell.
+    pub fn on_stop(&mut self, hook: impl FnMut() + Send + 'static) {
+        self.on_stop = Some(Box::new(hook));
+    }
     pub fn write_byte(&mut self, val: u8, condition: LineCondition) {
         if self.disengaged && condition != LineCondition::Start {
             
@@ -62,12 +80,12 @@ impl I2CSlave {
             
                 if self.disengaged && condition == LineCondition::Start {
                     self.disengaged = false;
-                    println!("Device [{:#x}] is reengaging the I2C bus.", self.address);
+                    self.trace.debug(TraceEvent::new("Reengaging the bus."));
                 } else if self.disengaged {
                     return; // We are disengaged.
                 }
                 self.state = SlaveState::ReadingAddress;
-                println!("[{:#x}] Device is beginning to receive data.", self.address);
+                self.trace.debug(TraceEvent::new("Beginning to receive data.").transition(SlaveState::Idle, self.state));
                 self.write_bit(bit, condition); // call this but in the new state.
             }
             SlaveState::ReadingAddress => {
@@ -76,17 +94,20 @@ impl I2CSlave {
                 if self.input_buffer.bits_read() == 8 {
                     let addr = self.input_buffer.read_byte().unwrap();
                     
-                    println!("[{:#x}] The device requested address: {:08b}", self.address, addr);
+                    if self.trace.enabled(Verbosity::Trace) {
+                        self.trace.trace(TraceEvent::new(format!("Requested address {:08b}.", addr)).value(addr as u32, Direction::MasterToSlave));
+                    }
                     if addr >> 1 == self.address {
                         // We are being addressed.
                         self.state = SlaveState::WaitingForRegisterAddress;
-                        self.output.write(false); // Acknowledge.
                         if addr & 0x01 == 1 {
-                            panic!("The R/W bit should have been zero.");
+                            self.reject("Reads need a register to be selected first.");
+                            return;
                         }
-                        println!("[{:#x}] Device is being addressed.", self.address);
+                        self.output.write(false); // Acknowledge.
+                        self.trace.debug(TraceEvent::new("Being addressed.").transition(SlaveState::ReadingAddress, self.state));
                     } else {
-                        println!("[{:#x}] Device is disengaging the I2C bus.", self.address);
+                        self.trace.debug(TraceEvent::new("Disengaging the bus.").transition(SlaveState::ReadingAddress, SlaveState::Idle));
                         self.disengaged = true; // Disengage this I2C device.
                         self.state = SlaveState::Idle; // Return to the IDLE state.
                     }
@@ -96,10 +117,13 @@ impl I2CSlave {
                 self.input_buffer.write(bit);
                 if self.input_buffer.bits_read() == 8 {
                     let register_address = self.input_buffer.read_byte().unwrap();
-                    println!("[{:#x}] Device requested register [{:#x}]", self.address, register_address);
+                    if self.trace.enabled(Verbosity::Debug) {
+                        self.trace.debug(TraceEvent::new(format!("Requested register [{:#x}].", register_address)).register(register_address as u32));
+                    }
 
                     if !self.registers.contains_key(&register_address) {
-                        panic!("No such register is on this device!");
+                        self.reject("No such register is on this device.");
+                        return;
                     }
 
                     self.reg_select = Some(register_address);
@@ -111,10 +135,10 @@ impl I2CSlave {
                 
             },
             SlaveState::WaitingForContinuation => {
-                if condition == LineCondition::InProgress {
-                    // If we are in-progress, we want to direct this call to the writing
-                    // methods.
-                    println!("[{:#x}] Device is starting write.", self.address);
+                if condition != LineCondition::Start {
+                    // Without a repeated start this is data, even when it is the
+                    // only byte before the stop.
+                    self.trace.debug(TraceEvent::new("Starting write.").transition(self.state, SlaveState::StartWrite));
                     self.state = SlaveState::StartWrite;
                     self.write_bit(bit, condition);
                     return;
@@ -125,13 +149,19 @@ impl I2CSlave {
 
                     
                     if (received & !(0x01)) >> 1 != self.address {
-                        panic!("The I2C slave did not receive the correct address.");
+                        self.reject("The repeated start went to another address.");
+                        return;
                     }
 
                     self.output.write(false);
 
                     if received & 0x01 == 1 {
-                        println!("[{:#x}] Starting read on register [{:#x}]", self.address, self.reg_select.unwrap());
+                        let register = self.reg_select.unwrap();
+                        if self.trace.enabled(Verbosity::Debug) {
+                            self.trace.debug(TraceEvent::new(format!("Starting read on register [{:#x}].", register))
+                                .register(register as u32)
+                                .transition(self.state, SlaveState::StartRead));
+                        }
 
                         // We simulate a register read by filling an internal register value.
                         self.registers.get_mut(&self.reg_select.unwrap()).unwrap().start_read();
@@ -147,8 +177,13 @@ impl I2CSlave {
             SlaveState::StartRead => {
                 // Read from the registers.
             
-                let byte = self.registers.get_mut(&self.reg_select.unwrap()).unwrap().read_byte();
-                self.output.write_byte(byte.unwrap());
+                let byte = self.registers.get_mut(&self.reg_select.unwrap()).unwrap().read_byte().unwrap();
+                if self.trace.enabled(Verbosity::Trace) {
+                    self.trace.trace(TraceEvent::new(format!("Sending {:#x}.", byte))
+                        .register(self.reg_select.unwrap() as u32)
+                        .value(byte as u32, Direction::SlaveToMaster));
+                }
+                self.output.write_byte(byte);
                 self.state = SlaveState::WaitingAckRead; // Waiting for an ACK
             }
             SlaveState::StartWrite => {
@@ -156,15 +191,20 @@ impl I2CSlave {
                 // Write the bit to the register
                 self.registers.get_mut(&self.reg_select.unwrap()).unwrap().write(bit);
                 
-                // Every seven bits send an acknowlegement.
-                self.input_buffer.write(false);
+                // Every eight bits send an acknowlegement.
+                self.input_buffer.write(bit);
                 if self.input_buffer.bits_read() == 8 {
-                    println!("[{:#x}] Write ACK", self.address);
+                    let byte = self.input_buffer.read_byte().unwrap();
+                    if self.trace.enabled(Verbosity::Trace) {
+                        self.trace.trace(TraceEvent::new(format!("Received {:#x}.", byte))
+                            .register(self.reg_select.unwrap() as u32)
+                            .value(byte as u32, Direction::MasterToSlave));
+                    }
+                    self.trace.trace(TraceEvent::new("Write ACK."));
                     self.output.write(false); // Write an acknowledgement.
-                    self.input_buffer.clear();
 
                     if condition == LineCondition::Stop {
-                        println!("[{:#x}] Device has received stop signal, write complete.", self.address);
+                        self.trace.debug(TraceEvent::new("Received stop, write complete.").transition(self.state, SlaveState::Idle));
                         self.state = SlaveState::Idle;
                     }
                 }
@@ -174,11 +214,11 @@ impl I2CSlave {
             SlaveState::WaitingAckRead => {
                 
                 if bit {
-                    println!("[{:#x}] Received a NACK from master.", self.address);
+                    self.trace.debug(TraceEvent::new("Received a NACK.").transition(self.state, SlaveState::Idle));
                     self.registers.get_mut(&self.reg_select.unwrap()).unwrap().finish_read();
                     self.state = SlaveState::Idle; // Return to the IDLE state.
                 } else {
-                    println!("[{:#x}] Byte transmission acknowledged.", self.address);
+                    self.trace.trace(TraceEvent::new("Byte acknowledged."));
                     self.state = SlaveState::StartRead; // Continue reading out registers.
                     self.write_bit(false, condition); // Send out some more data.
                 }
@@ -186,7 +226,28 @@ impl I2CSlave {
             }
         }
     }
-    
+    /// Leaves the byte unacknowledged and lets go of the bus until the next start.
+    fn reject(&mut self, reason: &str) {
+        self.trace.warn(TraceEvent::new(reason).transition(self.state, SlaveState::Idle));
+        self.input_buffer.clear();
+        self.disengaged = true;
+        self.state = SlaveState::Idle;
+    }
+    /// Puts the device back to idle, as a stop condition does.
+    pub fn reset(&mut self) {
+        if matches!(self.state, SlaveState::StartRead | SlaveState::WaitingAckRead)
+            && let Some(register) = self.reg_select.and_then(|r| self.registers.get_mut(&r))
+        {
+            register.finish_read();
+        }
+        self.state = SlaveState::Idle;
+        self.output.clear();
+        self.input_buffer.clear();
+        self.disengaged = false;
+        if let Some(hook) = &mut self.on_stop {
+            hook();
+        }
+    }
     pub fn read_bit(&mut self) -> Option<bool> {
         self.output.read()
     }
@@ -0,0 +1,27 @@
+use std::fmt;
+
+/// The errors a transfer on an I2C bus can end in.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum I2cError {
+    /// Nobody acknowledged a byte the master wrote.
+    Nack {
+        /// The byte of the transfer, counted from the first address byte.
+        byte: usize
+    },
+    /// Nobody answered when the master read a byte.
+    NoData {
+        /// The byte of the transfer, counted from the first address byte.
+        byte: usize
+    }
+}
+
+impl fmt::Display for I2cError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Nack { byte } => write!(f, "byte {byte} of the transfer was not acknowledged"),
+            Self::NoData { byte } => write!(f, "no device sent byte {byte} of the transfer")
+        }
+    }
+}
+
+impl std::error::Error for I2cError {}
@@ -0,0 +1,90 @@
+/// A fault an [I2CBus](super::I2CBus) can inject, see
+/// [I2CBus::set_faults](super::I2CBus::set_faults).
+///
+/// Faults fire on bytes, counted from zero over every byte on the bus
+/// whichever side sends it.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum I2cFault {
+    /// Flips a bit of the byte, bit 0 being the least significant one.
+    FlipBit(u8),
+    /// Nobody acknowledges the byte. Bytes sent by a slave are not affected.
+    Nack,
+    /// SDA is held low for the byte and its acknowledge, so the byte reads
+    /// as zero and is acknowledged.
+    HoldSdaLow,
+    /// The device with the given address drops off the bus for good, right
+    /// before the byte.
+    Vanish(u8)
+}
+
+impl I2cFault {
+    /// Applies the faults firing on a byte to its value.
+    pub(crate) fn corrupt(value: u8, faults: &[I2cFault]) -> u8 {
+        if faults.contains(&I2cFault::HoldSdaLow) {
+            return 0;
+        }
+        faults.iter().fold(value, |value, fault| match fault {
+            I2cFault::FlipBit(bit) => value ^ (1 << bit),
+            _ => value
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::{Arc, Mutex};
+
+    use crate::{core::{FaultPlan, Trigger}, i2c::{I2c, I2cError, I2cRecorder, Master}, testing};
+
+    use super::I2cFault;
+
+    /// Writes a register and reads it back, retrying a few times like a
+    /// driver would.
+    fn configure(bus: &mut impl I2c, value: u8) -> Result<(), I2cError> {
+        let mut last = Ok(());
+        for _ in 0..3 {
+            last = bus.try_write_block(0x68, 0x7D, vec![ value ])
+                .and_then(|_| bus.try_read_block(0x68, 0x7D, 1))
+                .map(|read| assert_eq!(read, vec![ value ]));
+            if last.is_ok() {
+                break;
+            }
+        }
+        last
+    }
+
+    fn bus() -> Master {
+        let mut master = Master::new();
+        master.add_device(testing::i2c_bmi270());
+        master
+    }
+
+    #[test]
+    pub fn test_recovery() {
+        // The register byte of the first write is not acknowledged.
+        let mut master = bus();
+        master.set_faults(FaultPlan::new().with(I2cFault::Nack, Trigger::Nth(1)));
+        let recorder = Arc::new(Mutex::new(I2cRecorder::new()));
+        master.add_probe(recorder.clone());
+        assert_eq!(configure(&mut master, 0x0E), Ok(()));
+        assert_eq!(master.faults().unwrap().fired(), &[(I2cFault::Nack, 1)]);
+        assert_eq!(recorder.lock().unwrap().records()[0].to_string(), "| Sr |[ 0x68 W ]( ACK )[ 0x7D ]( NACK )| St |");
+
+        // A flipped address bit makes the device ignore the write.
+        let mut master = bus();
+        master.set_faults(FaultPlan::new().with(I2cFault::FlipBit(1), Trigger::Nth(0)));
+        assert_eq!(master.try_write_block(0x68, 0x7D, vec![ 0x0E ]), Err(I2cError::Nack { byte: 0 }));
+        assert_eq!(configure(&mut master, 0x0E), Ok(()));
+
+        // SDA stuck low reads as zeros on every try.
+        let mut master = bus();
+        master.set_faults(FaultPlan::new().with(I2cFault::HoldSdaLow, Trigger::From(6)));
+        master.write_block(0x68, 0x7D, vec![ 0x0E ]);
+        assert_eq!(master.read_block(0x68, 0x7D, 1), vec![ 0x00 ]);
+
+        // The device drops off the bus in the middle of a read.
+        let mut master = bus();
+        master.set_faults(FaultPlan::new().with(I2cFault::Vanish(0x68), Trigger::Nth(3)));
+        assert_eq!(configure(&mut master, 0x0E), Err(I2cError::Nack { byte: 0 }));
+    }
+}
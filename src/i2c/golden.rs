@@ -0,0 +1,190 @@
+use std::{collections::{BTreeSet, HashMap, VecDeque}, fmt::{self, Display}, fs, io, path::Path, sync::{Arc, Mutex}};
+
+use crate::core::{GoldenMismatch, Register};
+
+use super::{I2CSlave, I2cRecord, I2cScript, I2cScriptError, I2cSegment, I2cStep, LineCondition};
+
+/// A recorded I2C session to check later runs against.
+///
+/// The session is stored in bracket notation, one transaction per line. It
+/// can be replayed by a slave answering exactly as recorded, or used to check
+/// that a driver still produces the same traffic.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct I2cGolden {
+    records: Vec<I2cRecord>
+}
+
+impl I2cGolden {
+    /// Creates a golden trace from recorded transactions, e.g. the ones of an
+    /// [I2cRecorder](super::I2cRecorder).
+    pub fn new(records: Vec<I2cRecord>) -> Self {
+        Self { records }
+    }
+    pub fn records(&self) -> &[I2cRecord] {
+        &self.records
+    }
+    /// Parses a golden trace in bracket notation, every value has to be given.
+    pub fn parse(text: &str) -> Result<Self, I2cScriptError> {
+        let script = I2cScript::parse(text)?;
+        let mut records = vec![];
+        let mut current: Option<I2cRecord> = None;
+        for &(step, line) in script.lines() {
+            let condition = match step {
+                I2cStep::Write { value, condition, ack: Some(ack) } => {
+                    let record = current.get_or_insert(I2cRecord { start: 0, end: 0, segments: vec![] });
+                    match (condition, record.segments.last_mut()) {
+                        (LineCondition::InProgress | LineCondition::Stop, Some(segment)) => {
+                            segment.data.push(value);
+                            segment.acks.push(ack);
+                        }
+                        _ => record.segments.push(I2cSegment {
+                            address: value >> 1,
+                            read: value & 0x01 != 0,
+                            address_ack: ack,
+                            data: vec![],
+                            acks: vec![]
+                        })
+                    }
+                    condition
+                }
+                I2cStep::Read { value: Some(value), ack, condition } => {
+                    if let Some(segment) = current.as_mut().and_then(|r| r.segments.last_mut()) {
+                        segment.data.push(value);
+                        segment.acks.push(ack);
+                    }
+                    condition
+                }
+                I2cStep::Stop => LineCondition::Stop,
+                _ => return Err(I2cScriptError::Syntax {
+                    line,
+                    message: "a golden trace cannot contain ( X )".to_string()
+                })
+            };
+            if condition == LineCondition::Stop {
+                records.extend(current.take());
+            }
+        }
+        Ok(Self { records })
+    }
+    /// Writes the trace to a file.
+    pub fn save(&self, path: impl AsRef<Path>) -> io::Result<()> {
+        fs::write(path, self.to_string())
+    }
+    /// Reads a trace from a file.
+    pub fn load(path: impl AsRef<Path>) -> io::Result<Self> {
+        Self::parse(&fs::read_to_string(path)?).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+    }
+
+    /// A slave that answers reads of a device exactly as recorded, one
+    /// recorded read after the other for every register. Writes are
+    /// acknowledged and dropped.
+    pub fn slave(&self, address: u8) -> I2CSlave {
+        let mut reads: HashMap<u8, VecDeque<Vec<u8>>> = HashMap::new();
+        for record in self.records.iter().filter(|r| r.address() == Some(address)) {
+            let Some(register) = record.register() else {
+                continue;
+            };
+            let queue = reads.entry(register).or_default();
+            if !record.is_write() {
+                queue.push_back(record.read());
+            }
+        }
+        let mut slave = I2CSlave::new(address);
+        for (register, queue) in reads {
+            let queue = Arc::new(Mutex::new(queue));
+            slave.create_register(register, Register::from_fn(move || {
+                queue.lock().unwrap().pop_front().unwrap_or_default()
+            }));
+        }
+        slave
+    }
+    /// A replaying slave for every device in the trace.
+    pub fn slaves(&self) -> Vec<I2CSlave> {
+        let addresses: BTreeSet<u8> = self.records.iter().filter_map(|r| r.address()).collect();
+        addresses.into_iter().map(|address| self.slave(address)).collect()
+    }
+    /// Checks that a run sent the same as the golden trace. Only the side of
+    /// the master is compared: addresses, bytes written, the amount of bytes
+    /// read and how the master acknowledged them.
+    pub fn verify(&self, records: &[I2cRecord]) -> Result<(), GoldenMismatch> {
+        GoldenMismatch::compare(&self.records, records, master_side, |r| r.to_string())
+    }
+}
+
+/// Everything in a transaction the master decides on.
+fn master_side(record: &I2cRecord) -> Vec<(u8, bool, Vec<u8>, Vec<bool>)> {
+    record.segments.iter()
+        .map(|s| if s.read {
+            (s.address, true, vec![0; s.data.len()], s.acks.clone())
+        } else {
+            (s.address, false, s.data.clone(), vec![])
+        })
+        .collect()
+}
+
+impl Display for I2cGolden {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for record in &self.records {
+            writeln!(f, "{}", record)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::{Arc, Mutex};
+
+    use crate::{i2c::{I2cRecorder, Master}, testing};
+
+    use super::I2cGolden;
+
+    #[test]
+    pub fn test_golden() {
+        fn driver(master: &mut Master, power: u8) -> u8 {
+            master.write_block(0x68, 0x7D, vec![ power ]);
+            master.read_block(0x68, 0x00, 1)[0]
+        }
+
+        // Record a run against the full device model.
+        let (mut master, recorder) = testing::i2c_bmi270_recorded();
+        driver(&mut master, 0x0E);
+        let golden = I2cGolden::new(recorder.lock().unwrap().take());
+        let golden = I2cGolden::parse(&golden.to_string()).unwrap();
+
+        // Replay it against the same driver.
+        let run = |power: u8| {
+            let mut master = Master::new();
+            for slave in golden.slaves() {
+                master.add_device(slave);
+            }
+            let recorder = Arc::new(Mutex::new(I2cRecorder::new()));
+            master.add_probe(recorder.clone());
+            let id = driver(&mut master, power);
+            let records = recorder.lock().unwrap().take();
+            (id, golden.verify(&records))
+        };
+        assert_eq!(run(0x0E), (0x24, Ok(())));
+
+        let (id, mismatch) = run(0x02);
+        assert_eq!(id, 0x24);
+        let mismatch = mismatch.unwrap_err();
+        assert_eq!(mismatch.index, 0);
+        assert_eq!(mismatch.actual.unwrap(), "| Sr |[ 0x68 W ]( ACK )[ 0x7D ]( ACK )[ 0x02 ]( ACK )| St |");
+    }
+
+    #[test]
+    pub fn test_save_nacked_address() {
+        let (mut master, recorder) = testing::i2c_bmi270_recorded();
+        assert!(master.try_write_block(0x69, 0x7D, vec![ 0x0E ]).is_err());
+        master.write_block(0x68, 0x7D, vec![ 0x0E ]);
+        let golden = I2cGolden::new(recorder.lock().unwrap().take());
+        assert!(!golden.records()[0].segments[0].address_ack);
+
+        let path = std::env::temp_dir().join(format!("i2cem-golden-{}.txt", std::process::id()));
+        golden.save(&path).unwrap();
+        let loaded = I2cGolden::load(&path);
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(loaded.unwrap().to_string(), golden.to_string());
+    }
+}
@@ -1,7 +1,28 @@
-use crate::i2c::LineCondition;
-
-use super::{I2CBus, I2CSlave};
-
+use crate::{core::{FaultPlan, Tracer}, i2c::LineCondition};
+
+use super::{I2CBus, I2CSlave, I2cError, I2cFault, I2cProbe};
+
+/// Register level access to devices on an I2C bus.
+///
+/// Drivers should be written against this trait so they work on a [Master]
+/// as well as on the proxies handed out by the shared buses.
+pub trait I2c {
+    /// Writes a block of bytes to a register of a device.
+    fn write_block(&mut self, device_addr: u8, reg_addr: u8, bytes: Vec<u8>);
+    /// Reads a block of bytes from a register of a device.
+    fn read_block(&mut self, device_addr: u8, reg_addr: u8, bytes: u8) -> Vec<u8>;
+    /// Writes a block of bytes, handing back an error instead of panicking
+    /// when the transfer fails.
+    fn try_write_block(&mut self, device_addr: u8, reg_addr: u8, bytes: Vec<u8>) -> Result<(), I2cError> {
+        self.write_block(device_addr, reg_addr, bytes);
+        Ok(())
+    }
+    /// Reads a block of bytes, handing back an error instead of panicking
+    /// when the transfer fails.
+    fn try_read_block(&mut self, device_addr: u8, reg_addr: u8, bytes: u8) -> Result<Vec<u8>, I2cError> {
+        Ok(self.read_block(device_addr, reg_addr, bytes))
+    }
+}
 
 pub struct Master {
     bus: I2CBus
@@ -16,56 +37,71 @@ impl Master {
     pub fn add_device(&mut self, device: I2CSlave) {
         self.bus.add_device(device);
     }
+    /// Attaches a probe to the bus, see [I2CBus::add_probe].
+    pub fn add_probe(&mut self, probe: impl I2cProbe + 'static) {
+        self.bus.add_probe(probe);
+    }
+    /// Reports what the devices on the bus are doing to the given [Tracer].
+    pub fn set_tracer(&mut self, tracer: Tracer) {
+        self.bus.set_tracer(tracer);
+    }
+    /// Injects faults into the bus from now on, see [I2CBus::set_faults].
+    pub fn set_faults(&mut self, faults: FaultPlan<I2cFault>) {
+        self.bus.set_faults(faults);
+    }
+    /// The faults injected, if any were set.
+    pub fn faults(&self) -> Option<&FaultPlan<I2cFault>> {
+        self.bus.faults()
+    }
     /// Writes a block of bytes from the I2C device.
     pub fn write_block(&mut self, device_addr: u8, reg_addr: u8, bytes: Vec<u8>) {
-        assert!(device_addr & 0x80 == 0, "Only 7-bit addressing is supported.");
-        assert!(reg_addr & 0x80 == 0, "Only 7-bit addressing is supported.");
-        // [ Slave Addr (7-bit) ] [ R/W bit = 0 ]
-        self.bus.write_byte(device_addr << 1, LineCondition::Start);
-        
-        // [ Register addr (7-bit) ]
-        self.bus.write_byte(reg_addr, LineCondition::InProgress);
-        
-
-
-        let mut i = (bytes.len() - 1) as isize;
-        while i >= 0 {
-
-            if i == 0 {
-                self.bus.write_byte(bytes[i as usize], LineCondition::Stop);
-            } else {
-                self.bus.write_byte(bytes[i as usize], LineCondition::InProgress);
-            }
-            i -= 1;
+        if let Err(error) = self.try_write_block(device_addr, reg_addr, bytes) {
+            panic!("{}", error);
         }
-
-       
-        
-
-
-        
-
     }
     /// Reads a block of bytes from the I2C device specified.
     pub fn read_block(&mut self, device_addr: u8, reg_addr: u8, bytes: u8) -> Vec<u8> {
+        self.try_read_block(device_addr, reg_addr, bytes).unwrap_or_else(|error| panic!("{}", error))
+    }
+    /// Writes a block of bytes, ending the transfer with a stop as soon as a
+    /// byte is not acknowledged.
+    pub fn try_write_block(&mut self, device_addr: u8, reg_addr: u8, bytes: Vec<u8>) -> Result<(), I2cError> {
+        assert!(device_addr & 0x80 == 0, "Only 7-bit addressing is supported.");
+        assert!(reg_addr & 0x80 == 0, "Only 7-bit addressing is supported.");
+        // [ Slave Addr (7-bit) ] [ R/W bit = 0 ] [ Register addr (7-bit) ]
+        let mut frame = vec![device_addr << 1, reg_addr];
+        // The data goes out last byte first.
+        frame.extend(bytes.into_iter().rev());
+        let last = frame.len() - 1;
+        for (byte, value) in frame.into_iter().enumerate() {
+            let condition = match byte {
+                0 => LineCondition::Start,
+                _ if byte == last => LineCondition::Stop,
+                _ => LineCondition::InProgress
+            };
+            self.send(byte, value, condition)?;
+        }
+        Ok(())
+    }
+    /// Reads a block of bytes, ending the transfer with a stop as soon as a
+    /// byte is not acknowledged or nobody answers.
+    pub fn try_read_block(&mut self, device_addr: u8, reg_addr: u8, bytes: u8) -> Result<Vec<u8>, I2cError> {
         assert!(device_addr & 0x80 == 0, "Only 7-bit addressing is supported.");
         assert!(reg_addr & 0x80 == 0, "Only 7-bit addressing is supported.");
         // [ Slave Addr (7-bit) ] [ R/W bit = 0 ]
-        self.bus.write_byte(device_addr << 1, LineCondition::Start);
-        
-
-        // println!("ODNE");
-
+        self.send(0, device_addr << 1, LineCondition::Start)?;
         // [ 0 bit ] [ Register addr (7-bit) ]
-        self.bus.write_byte(reg_addr, LineCondition::InProgress);
-        
-
+        self.send(1, reg_addr, LineCondition::InProgress)?;
         // [ Slave Addr (7-bit) ] [ R/W bit = 1 ]
-        self.bus.write_byte((device_addr << 1) | 0x01, LineCondition::Start);
+        self.send(2, (device_addr << 1) | 0x01, LineCondition::Start)?;
 
         let mut result = vec![];
         for i in 0..bytes {
-            result.push(self.bus.read_byte().unwrap());
+            let Some(value) = self.bus.read_byte() else {
+                self.bus.stop();
+                return Err(I2cError::NoData { byte: 3 + i as usize });
+            };
+            result.push(value);
             if i >= bytes - 1 {
                 // We are done.
                 self.bus.write_bit(true, LineCondition::Stop);
@@ -74,13 +110,36 @@ impl Master {
                 self.bus.write_bit(false, LineCondition::InProgress);
             }
         }
+        Ok(result)
+    }
+    /// Writes a byte of a transfer, stopping the transfer if it is not acknowledged.
+    fn send(&mut self, byte: usize, value: u8, condition: LineCondition) -> Result<(), I2cError> {
+        if self.bus.transmit(value, condition) {
+            return Ok(());
+        }
+        if condition != LineCondition::Stop {
+            self.bus.stop();
+        }
+        Err(I2cError::Nack { byte })
+    }
+}
 
 
-        result
+impl I2c for Master {
+    fn write_block(&mut self, device_addr: u8, reg_addr: u8, bytes: Vec<u8>) {
+        Master::write_block(self, device_addr, reg_addr, bytes);
+    }
+    fn read_block(&mut self, device_addr: u8, reg_addr: u8, bytes: u8) -> Vec<u8> {
+        Master::read_block(self, device_addr, reg_addr, bytes)
+    }
+    fn try_write_block(&mut self, device_addr: u8, reg_addr: u8, bytes: Vec<u8>) -> Result<(), I2cError> {
+        Master::try_write_block(self, device_addr, reg_addr, bytes)
+    }
+    fn try_read_block(&mut self, device_addr: u8, reg_addr: u8, bytes: u8) -> Result<Vec<u8>, I2cError> {
+        Master::try_read_block(self, device_addr, reg_addr, bytes)
     }
 }
 
-
 impl Default for Master {
     fn default() -> Self {
         Self::new()
@@ -0,0 +1,190 @@
+use std::{fmt::{self, Display}, sync::{Arc, Mutex}};
+
+use crate::core::{mock::Expectations, Register};
+
+use super::I2CSlave;
+
+/// A register access an [I2cMock] expects, in the terms of the
+/// [I2c](super::I2c) trait.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum I2cExpectation {
+    Write { register: u8, bytes: Vec<u8> },
+    /// A read, answered with `response`.
+    Read { register: u8, response: Vec<u8> }
+}
+
+impl I2cExpectation {
+    pub fn write(register: u8, bytes: Vec<u8>) -> Self {
+        Self::Write { register, bytes }
+    }
+    pub fn read(register: u8, response: Vec<u8>) -> Self {
+        Self::Read { register, response }
+    }
+}
+
+impl Display for I2cExpectation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            I2cExpectation::Write { register, bytes } => write!(f, "write [{:#04X}] {:02X?}", register, bytes),
+            I2cExpectation::Read { register, response } => write!(f, "read [{:#04X}] -> {:02X?}", register, response)
+        }
+    }
+}
+
+/// Holds a driver to a scripted sequence of register writes and reads at a
+/// single address, answering the reads with the bytes given.
+///
+/// The device on the bus is a regular [I2CSlave], so addressing and
+/// acknowledging work exactly like they do for a modelled device. When the
+/// mock is dropped it panics with a diff if anything was unexpected or
+/// missing, call [I2cMock::done] to check earlier.
+pub struct I2cMock {
+    address: u8,
+    state: Arc<Mutex<MockState>>
+}
+
+struct MockState {
+    expectations: Expectations<I2cExpectation>,
+    /// The register and the bytes of a write still in progress, in the
+    /// order they crossed the bus.
+    writing: Option<(u8, Vec<u8>)>
+}
+
+impl MockState {
+    /// Checks the write in progress.
+    fn finish_write(&mut self) {
+        if let Some((register, mut bytes)) = self.writing.take() {
+            // The master sends a block last byte first.
+            bytes.reverse();
+            self.expectations.check(I2cExpectation::Write { register, bytes }, |e, a| e == a);
+        }
+    }
+    fn on_byte(&mut self, register: u8, byte: u8) {
+        if self.writing.as_ref().is_some_and(|(r, _)| *r != register) {
+            self.finish_write();
+        }
+        let (_, bytes) = self.writing.get_or_insert((register, vec![]));
+        bytes.push(byte);
+    }
+    fn on_read(&mut self, register: u8) -> Vec<u8> {
+        self.finish_write();
+        let matches = |e: &I2cExpectation, a: &I2cExpectation| matches!(
+            (e, a),
+            (I2cExpectation::Read { register: e, .. }, I2cExpectation::Read { register: a, .. }) if e == a
+        );
+        match self.expectations.check(I2cExpectation::Read { register, response: vec![] }, matches) {
+            Some(I2cExpectation::Read { response, .. }) => response,
+            _ => vec![]
+        }
+    }
+}
+
+impl I2cMock {
+    pub fn new(address: u8, expectations: impl IntoIterator<Item = I2cExpectation>) -> Self {
+        Self {
+            address,
+            state: Arc::new(Mutex::new(MockState {
+                expectations: Expectations::new(expectations),
+                writing: None
+            }))
+        }
+    }
+    /// The device to put on the bus, every register of it reports to the mock.
+    pub fn slave(&self) -> I2CSlave {
+        let mut slave = I2CSlave::new(self.address);
+        for register in 0..0x80 {
+            let (read, write) = (self.state.clone(), self.state.clone());
+            slave.create_register(register, Register::from_fn(move || read.lock().unwrap().on_read(register))
+                .on_write(move |byte| write.lock().unwrap().on_byte(register, byte)));
+        }
+        // A write is only over once the master lets go of the bus.
+        let state = self.state.clone();
+        slave.on_stop(move || state.lock().unwrap().finish_write());
+        slave
+    }
+    /// A diff of the expected and the actual accesses, if they differ.
+    pub fn report(&self) -> Option<String> {
+        let mut state = self.state.lock().unwrap();
+        state.finish_write();
+        state.expectations.checked = true;
+        state.expectations.report()
+    }
+    /// Panics with a diff if the accesses did not go as expected.
+    pub fn done(&mut self) {
+        if let Some(diff) = self.report() {
+            panic!("I2C mock {:#04X} saw something else than expected:\n{}", self.address, diff);
+        }
+    }
+}
+
+impl Drop for I2cMock {
+    fn drop(&mut self) {
+        let checked = self.state.lock().unwrap().expectations.checked;
+        if !checked && !std::thread::panicking() {
+            self.done();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::i2c::Master;
+
+    use super::{I2cExpectation, I2cMock};
+
+    #[test]
+    pub fn test_mock() {
+        let mut mock = I2cMock::new(0x68, [
+            I2cExpectation::read(0x00, vec![ 0x24 ]),
+            I2cExpectation::write(0x7D, vec![ 0x02, 0x0E ]),
+            I2cExpectation::read(0x7D, vec![ 0x0E, 0x02 ])
+        ]);
+        let mut master = Master::new();
+        master.add_device(mock.slave());
+
+        assert_eq!(master.read_block(0x68, 0x00, 1), vec![ 0x24 ]);
+        master.write_block(0x68, 0x7D, vec![ 0x02, 0x0E ]);
+        assert_eq!(master.read_block(0x68, 0x7D, 2), vec![ 0x0E, 0x02 ]);
+        mock.done();
+    }
+
+    #[test]
+    pub fn test_mock_diff() {
+        let mock = I2cMock::new(0x68, [
+            I2cExpectation::write(0x7D, vec![ 0x0E ]),
+            I2cExpectation::read(0x00, vec![ 0x24 ]),
+            I2cExpectation::read(0x01, vec![ 0x00 ])
+        ]);
+        let mut master = Master::new();
+        master.add_device(mock.slave());
+
+        master.write_block(0x68, 0x7D, vec![ 0x0E ]);
+        master.read_block(0x68, 0x02, 1);
+
+        assert_eq!(mock.report().unwrap(), [
+            "  write [0x7D] [0E]",
+            "- read [0x00] -> [24]",
+            "+ read [0x02] -> []",
+            "- read [0x01] -> [00]"
+        ].join("\n"));
+    }
+
+    #[test]
+    pub fn test_mock_split_write() {
+        let mock = I2cMock::new(0x68, [
+            I2cExpectation::write(0x7D, vec![ 0x0E, 0x02 ])
+        ]);
+        let mut master = Master::new();
+        master.add_device(mock.slave());
+
+        // Two single byte writes are not one write of two bytes.
+        master.write_block(0x68, 0x7D, vec![ 0x02 ]);
+        master.write_block(0x68, 0x7D, vec![ 0x0E ]);
+
+        assert_eq!(mock.report().unwrap(), [
+            "- write [0x7D] [0E, 02]",
+            "+ write [0x7D] [02]",
+            "+ write [0x7D] [0E]"
+        ].join("\n"));
+    }
+}
@@ -1,7 +1,27 @@
 pub mod bus;
+pub mod decode;
 pub mod device;
+pub mod error;
+pub mod fault;
+pub mod golden;
 pub mod master;
+pub mod mock;
+pub mod notation;
+pub mod shared;
+pub mod probe;
+pub mod record;
+pub mod waveform;
 
 pub use bus::*;
+pub use decode::*;
 pub use device::*;
+pub use error::*;
+pub use fault::*;
+pub use golden::*;
 pub use master::*;
+pub use mock::*;
+pub use notation::*;
+pub use shared::*;
+pub use probe::*;
+pub use record::*;
+pub use waveform::*;
@@ -0,0 +1,366 @@
+use std::{fmt::{self, Display}, str::FromStr};
+
+use super::{I2CBus, I2cRecord, I2cRecorder, I2cSegment, LineCondition};
+
+/// Renders a segment the way the README describes the protocol, e.g.
+/// `[ 0x68 W ]( ACK )[ 0x7D ]( ACK )`. Whatever the master sends is in `[]`,
+/// whatever the slave sends is in `()`.
+impl Display for I2cSegment {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let ack = |ack: bool| if ack { "ACK" } else { "NACK" };
+        write!(f, "[ {:#04X} {} ]", self.address, if self.read { "R" } else { "W" })?;
+        write!(f, "( {} )", ack(self.address_ack))?;
+        for (value, &acked) in self.data.iter().zip(&self.acks) {
+            if self.read {
+                write!(f, "( {:#04X} )[ {} ]", value, ack(acked))?;
+            } else {
+                write!(f, "[ {:#04X} ]( {} )", value, ack(acked))?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Renders a whole transaction, e.g.
+/// `| Sr |[ 0x68 W ]( ACK )[ 0x00 ]( ACK )| Sr |[ 0x68 R ]( ACK )( 0x24 )[ NACK ]| St |`.
+impl Display for I2cRecord {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for segment in &self.segments {
+            write!(f, "| Sr |{}", segment)?;
+        }
+        write!(f, "| St |")
+    }
+}
+
+impl I2cRecorder {
+    /// Every completed transaction in bracket notation, one per line.
+    pub fn diagram(&self) -> String {
+        self.records().iter().map(|r| r.to_string()).collect::<Vec<_>>().join("\n")
+    }
+}
+
+/// A single byte of a [I2cScript] along with its acknowledge bit.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum I2cStep {
+    /// The master sends a byte, `ack` is what the slave should answer.
+    Write { value: u8, condition: LineCondition, ack: Option<bool> },
+    /// The slave sends a byte, `value` is what it should send and `ack` is
+    /// what the master answers.
+    Read { value: Option<u8>, ack: bool, condition: LineCondition },
+    /// The master ends the transaction right after the address, only used
+    /// when no byte follows it that could carry the stop.
+    Stop
+}
+
+/// Why a script could not be parsed or did not run as written.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum I2cScriptError {
+    /// The script is not valid notation, `line` counts from 1.
+    Syntax { line: usize, message: String },
+    /// The slave answered something else than the script expects.
+    Mismatch { line: usize, expected: String, actual: String }
+}
+
+impl Display for I2cScriptError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            I2cScriptError::Syntax { line, message } => write!(f, "line {}: {}", line, message),
+            I2cScriptError::Mismatch { line, expected, actual } => {
+                write!(f, "line {}: expected {} but the slave sent {}", line, expected, actual)
+            }
+        }
+    }
+}
+
+impl std::error::Error for I2cScriptError {}
+
+/// A protocol level scenario written in the bracket notation of the README.
+///
+/// ```text
+/// # Read the chip ID.
+/// | Sr |[ 0x68 W ]( ACK )[ 0x00 ]( ACK )| Sr |[ 0x68 R ]( ACK )( 0x24 )[ NACK ]| St |
+/// ```
+///
+/// Whatever is in `[]` is sent by the master, whatever is in `()` is what the
+/// slave is expected to send, `( X )` accepts anything. Values can be written
+/// in hex, binary or decimal and `#` starts a comment.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct I2cScript {
+    /// Every step along with the line it was written on.
+    steps: Vec<(I2cStep, usize)>
+}
+
+/// A bracketed part of a script.
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Group<'a> {
+    Condition(&'a str),
+    Master(&'a str),
+    Slave(&'a str)
+}
+
+impl I2cScript {
+    pub fn parse(script: &str) -> Result<Self, I2cScriptError> {
+        let groups = tokenize(script)?;
+        let syntax = |line: usize, message: &str| I2cScriptError::Syntax { line, message: message.to_string() };
+        let mut steps: Vec<(I2cStep, usize)> = vec![];
+        let mut groups = groups.into_iter().peekable();
+        let mut active = false;
+
+        while let Some((group, line)) = groups.next() {
+            match group {
+                Group::Condition("Sr") => {
+                    let Some((Group::Master(address), line)) = groups.next() else {
+                        return Err(syntax(line, "expected the address in [] after a start"));
+                    };
+                    let (address, read) = match address.split_whitespace().collect::<Vec<_>>()[..] {
+                        [address, "W"] => (address, false),
+                        [address, "R"] => (address, true),
+                        _ => return Err(syntax(line, "expected an address followed by R or W"))
+                    };
+                    let address = parse_value(address).filter(|a| *a < 0x80)
+                        .ok_or_else(|| syntax(line, "expected a 7-bit address"))?;
+                    let ack = match groups.next() {
+                        Some((Group::Slave(ack), line)) => parse_ack(ack).ok_or_else(|| syntax(line, "expected ( ACK ), ( NACK ) or ( X )"))?,
+                        _ => return Err(syntax(line, "expected the acknowledge of the slave after the address"))
+                    };
+                    steps.push((I2cStep::Write { value: address << 1 | read as u8, condition: LineCondition::Start, ack }, line));
+                    active = true;
+
+                    // The data bytes up to the next start or stop.
+                    while let Some(&(group, line)) = groups.peek() {
+                        let step = match (group, read) {
+                            (Group::Master(value), false) => {
+                                let value = parse_value(value).ok_or_else(|| syntax(line, "expected a byte"))?;
+                                groups.next();
+                                let ack = match groups.next() {
+                                    Some((Group::Slave(ack), line)) => parse_ack(ack).ok_or_else(|| syntax(line, "expected ( ACK ), ( NACK ) or ( X )"))?,
+                                    _ => return Err(syntax(line, "expected the acknowledge of the slave after a byte"))
+                                };
+                                I2cStep::Write { value, condition: LineCondition::InProgress, ack }
+                            }
+                            (Group::Slave(value), true) => {
+                                let value = match value {
+                                    "X" => None,
+                                    value => Some(parse_value(value).ok_or_else(|| syntax(line, "expected a byte or X"))?)
+                                };
+                                groups.next();
+                                let ack = match groups.next() {
+                                    Some((Group::Master("ACK"), _)) => true,
+                                    Some((Group::Master("NACK"), _)) => false,
+                                    _ => return Err(syntax(line, "expected [ ACK ] or [ NACK ] after a byte read"))
+                                };
+                                I2cStep::Read { value, ack, condition: LineCondition::InProgress }
+                            }
+                            (Group::Condition(_), _) => break,
+                            (_, true) => return Err(syntax(line, "only the slave sends bytes after a read address")),
+                            (_, false) => return Err(syntax(line, "only the master sends bytes after a write address"))
+                        };
+                        steps.push((step, line));
+                    }
+                }
+                Group::Condition("St") => {
+                    if !active {
+                        return Err(syntax(line, "a stop without a start"));
+                    }
+                    // The bus model takes the stop along with the last byte.
+                    match steps.last_mut() {
+                        Some((I2cStep::Write { condition: condition @ LineCondition::InProgress, .. }, _))
+                        | Some((I2cStep::Read { condition: condition @ LineCondition::InProgress, .. }, _)) => {
+                            *condition = LineCondition::Stop;
+                        }
+                        // An address-only transaction, e.g. probing for a device.
+                        _ => steps.push((I2cStep::Stop, line))
+                    }
+                    active = false;
+                }
+                Group::Condition(_) => return Err(syntax(line, "expected | Sr | or | St |")),
+                _ => return Err(syntax(line, "expected | Sr | to start a transaction"))
+            }
+        }
+        if let (true, Some(&(_, line))) = (active, steps.last()) {
+            return Err(syntax(line, "the last transaction has no stop"));
+        }
+        Ok(Self { steps })
+    }
+    pub fn steps(&self) -> impl Iterator<Item = &I2cStep> {
+        self.steps.iter().map(|(step, _)| step)
+    }
+    /// Every step along with the line it was written on.
+    pub(crate) fn lines(&self) -> &[(I2cStep, usize)] {
+        &self.steps
+    }
+    /// Runs the script against the devices on a bus and returns the bytes read,
+    /// stopping at the first answer that does not match.
+    ///
+    /// A mismatch ends the transaction with a stop, so the bus is idle for
+    /// whatever runs next.
+    pub fn run(&self, bus: &mut I2CBus) -> Result<Vec<u8>, I2cScriptError> {
+        let result = self.run_steps(bus);
+        if result.is_err() {
+            bus.stop();
+        }
+        result
+    }
+    fn run_steps(&self, bus: &mut I2CBus) -> Result<Vec<u8>, I2cScriptError> {
+        let ack = |ack: bool| if ack { "( ACK )" } else { "( NACK )" }.to_string();
+        let mut read = vec![];
+        for &(step, line) in &self.steps {
+            match step {
+                I2cStep::Write { value, condition, ack: expected } => {
+                    let actual = bus.transmit(value, condition);
+                    if expected.is_some_and(|expected| expected != actual) {
+                        return Err(I2cScriptError::Mismatch { line, expected: ack(!actual), actual: ack(actual) });
+                    }
+                }
+                I2cStep::Read { value: expected, ack, condition } => {
+                    let actual = bus.read_byte();
+                    match actual {
+                        Some(actual) if expected.is_none_or(|expected| expected == actual) => read.push(actual),
+                        _ => return Err(I2cScriptError::Mismatch {
+                            line,
+                            expected: expected.map_or("a byte".to_string(), |e| format!("( {:#04X} )", e)),
+                            actual: actual.map_or("nothing".to_string(), |a| format!("( {:#04X} )", a))
+                        })
+                    }
+                    bus.write_bit(!ack, condition);
+                }
+                I2cStep::Stop => bus.stop()
+            }
+        }
+        Ok(read)
+    }
+}
+
+impl FromStr for I2cScript {
+    type Err = I2cScriptError;
+
+    fn from_str(script: &str) -> Result<Self, Self::Err> {
+        Self::parse(script)
+    }
+}
+
+/// Splits a script into its bracketed groups along with their lines.
+fn tokenize(script: &str) -> Result<Vec<(Group<'_>, usize)>, I2cScriptError> {
+    let mut groups = vec![];
+    for (index, line) in script.lines().enumerate() {
+        let mut rest = line.split('#').next().unwrap_or_default().trim_start();
+        while let Some(open) = rest.chars().next() {
+            let close = match open {
+                '|' => '|',
+                '[' => ']',
+                '(' => ')',
+                _ => return Err(I2cScriptError::Syntax {
+                    line: index + 1,
+                    message: format!("unexpected '{}'", open)
+                })
+            };
+            let end = rest[1..].find(close).ok_or_else(|| I2cScriptError::Syntax {
+                line: index + 1,
+                message: format!("'{}' is never closed", open)
+            })? + 1;
+            let content = rest[1..end].trim();
+            groups.push((match open {
+                '|' => Group::Condition(content),
+                '[' => Group::Master(content),
+                _ => Group::Slave(content)
+            }, index + 1));
+            rest = rest[end + 1..].trim_start();
+        }
+    }
+    Ok(groups)
+}
+
+fn parse_value(value: &str) -> Option<u8> {
+    if let Some(hex) = value.strip_prefix("0x").or_else(|| value.strip_prefix("0X")) {
+        u8::from_str_radix(hex, 16).ok()
+    } else if let Some(binary) = value.strip_prefix("0b") {
+        u8::from_str_radix(binary, 2).ok()
+    } else {
+        value.parse().ok()
+    }
+}
+
+/// Parses the acknowledge of a slave, `None` for don't care.
+fn parse_ack(ack: &str) -> Option<Option<bool>> {
+    match ack {
+        "ACK" => Some(Some(true)),
+        "NACK" => Some(Some(false)),
+        "X" => Some(None),
+        _ => None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::{Arc, Mutex};
+
+    use crate::{i2c::{I2CBus, I2cRecorder}, testing};
+
+    use super::{I2cScript, I2cScriptError};
+
+    #[test]
+    pub fn test_diagram() {
+        let (mut master, recorder) = testing::i2c_bmi270_recorded();
+
+        master.write_block(0x68, 0x7D, vec![ 0x02, 0x0E ]);
+        master.read_block(0x68, 0x00, 1);
+
+        assert_eq!(recorder.lock().unwrap().diagram(), [
+            "| Sr |[ 0x68 W ]( ACK )[ 0x7D ]( ACK )[ 0x0E ]( ACK )[ 0x02 ]( ACK )| St |",
+            "| Sr |[ 0x68 W ]( ACK )[ 0x00 ]( ACK )| Sr |[ 0x68 R ]( ACK )( 0x24 )[ NACK ]| St |"
+        ].join("\n"));
+    }
+
+    #[test]
+    pub fn test_script() {
+        let mut bus = I2CBus::new();
+        bus.add_device(testing::i2c_bmi270());
+
+        let script: I2cScript = "
+            # Enable the sensor, then read it back along with the chip ID.
+            | Sr |[ 0x68 W ]( ACK )[ 0x7D ]( ACK )[ 0x0E ]( ACK )| St |
+            | Sr |[ 0x68 W ]( ACK )[ 0x7D ]( ACK )| Sr |[ 0x68 R ]( ACK )( 0x0E )[ NACK ]| St |
+            | Sr |[ 0x68 W ]( ACK )[ 0x00 ]( ACK )| Sr |[ 0x68 R ]( ACK )( X )[ NACK ]| St |
+        ".parse().unwrap();
+        assert_eq!(script.run(&mut bus), Ok(vec![ 0x0E, 0x24 ]));
+
+        let script = I2cScript::parse("| Sr |[ 0x68 W ]( ACK )[ 0x00 ]( ACK )| Sr |[ 0x68 R ]( ACK )( 0x25 )[ NACK ]| St |").unwrap();
+        assert_eq!(script.run(&mut bus), Err(I2cScriptError::Mismatch {
+            line: 1,
+            expected: "( 0x25 )".to_string(),
+            actual: "( 0x24 )".to_string()
+        }));
+        // The failed script let go of the bus, so the next one runs as usual.
+        let script = I2cScript::parse("| Sr |[ 0x68 W ]( ACK )[ 0x7D ]( ACK )| Sr |[ 0x68 R ]( ACK )( 0x0E )[ NACK ]| St |").unwrap();
+        assert_eq!(script.run(&mut bus), Ok(vec![ 0x0E ]));
+
+        // Also after a write that went unacknowledged mid-transaction.
+        let script = I2cScript::parse("| Sr |[ 0x68 W ]( ACK )[ 0x42 ]( ACK )[ 0x01 ]( ACK )| St |").unwrap();
+        assert_eq!(script.run(&mut bus), Err(I2cScriptError::Mismatch {
+            line: 1,
+            expected: "( ACK )".to_string(),
+            actual: "( NACK )".to_string()
+        }));
+        let script = I2cScript::parse("| Sr |[ 0x68 W ]( ACK )[ 0x00 ]( ACK )| Sr |[ 0x68 R ]( ACK )( 0x24 )[ NACK ]| St |").unwrap();
+        assert_eq!(script.run(&mut bus), Ok(vec![ 0x24 ]));
+
+        assert!(matches!(I2cScript::parse("| Sr |[ 0x68 W ]\n( ACK )[ 0x00 ( ACK )"), Err(I2cScriptError::Syntax { line: 2, .. })));
+    }
+
+    #[test]
+    pub fn test_address_only() {
+        let mut bus = I2CBus::new();
+        bus.add_device(testing::i2c_bmi270());
+        let recorder = Arc::new(Mutex::new(I2cRecorder::new()));
+        bus.add_probe(recorder.clone());
+
+        // Probing an address nobody answers to, then one that is there.
+        let script = I2cScript::parse("| Sr |[ 0x69 W ]( NACK )| St |\n| Sr |[ 0x68 W ]( ACK )| St |").unwrap();
+        assert_eq!(script.run(&mut bus), Ok(vec![]));
+
+        // The recorded diagram reads back into the same script.
+        let diagram = recorder.lock().unwrap().diagram();
+        assert_eq!(diagram, "| Sr |[ 0x69 W ]( NACK )| St |\n| Sr |[ 0x68 W ]( ACK )| St |");
+        assert_eq!(I2cScript::parse(&diagram), Ok(script));
+    }
+}
@@ -0,0 +1,47 @@
+use std::sync::{Arc, Mutex};
+
+/// Something that happened on an [I2CBus](super::I2CBus), in the order it
+/// happened on the wire.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum I2cEvent {
+    /// A start condition, or a repeated start inside a transaction.
+    Start,
+    /// A byte, most significant bit first.
+    Byte {
+        value: u8,
+        /// If the master drove SDA, otherwise a slave did.
+        from_master: bool
+    },
+    /// The acknowledge bit following a byte.
+    Ack {
+        /// ACK when SDA was pulled low, NACK otherwise.
+        ack: bool,
+        /// If the master drove SDA, otherwise a slave did.
+        from_master: bool
+    },
+    Stop
+}
+
+impl I2cEvent {
+    /// The SCL cycles the event takes up on the wire.
+    pub fn cycles(&self) -> u64 {
+        match self {
+            I2cEvent::Byte { .. } => 8,
+            _ => 1
+        }
+    }
+}
+
+/// Watches the events on an [I2CBus](super::I2CBus).
+pub trait I2cProbe: Send {
+    /// Called for every event, `cycle` is the SCL cycle it started at.
+    fn on_event(&mut self, event: &I2cEvent, cycle: u64);
+}
+
+/// Lets a probe such as an [I2cRecorder](super::I2cRecorder) sit on the bus
+/// while the caller keeps a handle to read it out.
+impl<P: I2cProbe> I2cProbe for Arc<Mutex<P>> {
+    fn on_event(&mut self, event: &I2cEvent, cycle: u64) {
+        self.lock().unwrap().on_event(event, cycle);
+    }
+}
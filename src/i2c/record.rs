@@ -0,0 +1,161 @@
+use super::{I2cEvent, I2cProbe};
+
+/// The part of a transaction between a (repeated) start and the next one.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct I2cSegment {
+    /// The 7-bit address of the device.
+    pub address: u8,
+    pub read: bool,
+    /// If a device acknowledged the address.
+    pub address_ack: bool,
+    /// The bytes that followed the address, in the order they crossed the bus.
+    pub data: Vec<u8>,
+    /// The acknowledge bit after every data byte.
+    pub acks: Vec<bool>
+}
+
+/// Everything from a start condition up to the stop condition.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct I2cRecord {
+    /// The SCL cycle of the start condition.
+    pub start: u64,
+    /// The SCL cycle of the stop condition.
+    pub end: u64,
+    pub segments: Vec<I2cSegment>
+}
+
+impl I2cRecord {
+    /// The address of the first segment.
+    pub fn address(&self) -> Option<u8> {
+        self.segments.first().map(|s| s.address)
+    }
+    /// The register selected by the first byte written.
+    pub fn register(&self) -> Option<u8> {
+        self.segments.iter().find(|s| !s.read)?.data.first().copied()
+    }
+    /// Checks if nothing was read.
+    pub fn is_write(&self) -> bool {
+        self.segments.iter().all(|s| !s.read)
+    }
+    /// The bytes written after the register.
+    pub fn written(&self) -> Vec<u8> {
+        self.segments.iter().filter(|s| !s.read).flat_map(|s| s.data.iter().copied()).skip(1).collect()
+    }
+    /// The bytes read back.
+    pub fn read(&self) -> Vec<u8> {
+        self.segments.iter().filter(|s| s.read).flat_map(|s| s.data.iter().copied()).collect()
+    }
+}
+
+/// Records every transaction on an I2C bus, attach it with
+/// [I2CBus::add_probe](super::I2CBus::add_probe) behind an `Arc<Mutex<_>>`.
+#[derive(Default)]
+pub struct I2cRecorder {
+    records: Vec<I2cRecord>,
+    /// The transaction still in progress.
+    current: Option<I2cRecord>,
+    /// If the next byte is an address.
+    expecting_address: bool
+}
+
+impl I2cRecorder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+    /// Every completed transaction.
+    pub fn records(&self) -> &[I2cRecord] {
+        &self.records
+    }
+    /// Takes the completed transactions.
+    pub fn take(&mut self) -> Vec<I2cRecord> {
+        std::mem::take(&mut self.records)
+    }
+    /// The bytes of every write to a register of a device.
+    pub fn writes_to(&self, address: u8, register: u8) -> Vec<Vec<u8>> {
+        self.records.iter()
+            .filter(|r| r.is_write() && r.address() == Some(address) && r.register() == Some(register))
+            .map(|r| r.written())
+            .collect()
+    }
+    /// The bytes of every read from a register of a device.
+    pub fn reads_from(&self, address: u8, register: u8) -> Vec<Vec<u8>> {
+        self.records.iter()
+            .filter(|r| !r.is_write() && r.address() == Some(address) && r.register() == Some(register))
+            .map(|r| r.read())
+            .collect()
+    }
+}
+
+impl I2cProbe for I2cRecorder {
+    fn on_event(&mut self, event: &I2cEvent, cycle: u64) {
+        match *event {
+            I2cEvent::Start => {
+                self.current.get_or_insert(I2cRecord { start: cycle, end: cycle, segments: vec![] });
+                self.expecting_address = true;
+            }
+            I2cEvent::Byte { value, .. } => {
+                let Some(record) = &mut self.current else {
+                    return;
+                };
+                if self.expecting_address {
+                    self.expecting_address = false;
+                    record.segments.push(I2cSegment {
+                        address: value >> 1,
+                        read: value & 0x01 != 0,
+                        address_ack: false,
+                        data: vec![],
+                        acks: vec![]
+                    });
+                } else if let Some(segment) = record.segments.last_mut() {
+                    segment.data.push(value);
+                }
+            }
+            I2cEvent::Ack { ack, .. } => {
+                if let Some(segment) = self.current.as_mut().and_then(|r| r.segments.last_mut()) {
+                    if segment.data.len() > segment.acks.len() {
+                        segment.acks.push(ack);
+                    } else {
+                        segment.address_ack = ack;
+                    }
+                }
+            }
+            I2cEvent::Stop => {
+                if let Some(mut record) = self.current.take() {
+                    record.end = cycle;
+                    self.records.push(record);
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::testing;
+
+    use super::I2cSegment;
+
+    #[test]
+    pub fn test_recorder() {
+        let (mut master, recorder) = testing::i2c_bmi270_recorded();
+
+        master.write_block(0x68, 0x7D, vec![ 0x02, 0x0E ]);
+        assert_eq!(master.read_block(0x68, 0x00, 1), vec![ 0x24 ]);
+
+        let recorder = recorder.lock().unwrap();
+        // Data goes out last byte first so the registers read it back in order.
+        assert_eq!(recorder.writes_to(0x68, 0x7D), vec![ vec![ 0x0E, 0x02 ] ]);
+        assert_eq!(recorder.reads_from(0x68, 0x00), vec![ vec![ 0x24 ] ]);
+
+        let read = &recorder.records()[1];
+        assert_eq!(read.segments[1], I2cSegment {
+            address: 0x68,
+            read: true,
+            address_ack: true,
+            data: vec![ 0x24 ],
+            acks: vec![ false ]
+        });
+        // Start, two bytes with their ACKs, a repeated start, a byte with its ACK.
+        assert_eq!(read.end - read.start, 1 + 18 + 1 + 9 + 9);
+    }
+}
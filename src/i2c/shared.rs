@@ -0,0 +1,149 @@
+use std::{cell::RefCell, sync::Mutex};
+
+use super::{I2c, I2cError};
+
+/// A bus that can be shared between threads, every driver gets its own
+/// [MutexDevice] and each block transfer holds the lock for its duration.
+pub struct MutexBus<T> {
+    bus: Mutex<T>
+}
+
+impl<T: I2c> MutexBus<T> {
+    pub fn new(bus: T) -> Self {
+        Self { bus: Mutex::new(bus) }
+    }
+    /// Hands out a proxy for a single driver.
+    pub fn proxy(&self) -> MutexDevice<'_, T> {
+        MutexDevice { bus: &self.bus }
+    }
+    /// Gives the bus back once every proxy is gone.
+    pub fn into_inner(self) -> T {
+        self.bus.into_inner().unwrap()
+    }
+}
+
+/// A single driver's view of a [MutexBus].
+pub struct MutexDevice<'a, T> {
+    bus: &'a Mutex<T>
+}
+
+impl<T: I2c> I2c for MutexDevice<'_, T> {
+    fn write_block(&mut self, device_addr: u8, reg_addr: u8, bytes: Vec<u8>) {
+        self.bus.lock().unwrap().write_block(device_addr, reg_addr, bytes);
+    }
+    fn read_block(&mut self, device_addr: u8, reg_addr: u8, bytes: u8) -> Vec<u8> {
+        self.bus.lock().unwrap().read_block(device_addr, reg_addr, bytes)
+    }
+    fn try_write_block(&mut self, device_addr: u8, reg_addr: u8, bytes: Vec<u8>) -> Result<(), I2cError> {
+        self.bus.lock().unwrap().try_write_block(device_addr, reg_addr, bytes)
+    }
+    fn try_read_block(&mut self, device_addr: u8, reg_addr: u8, bytes: u8) -> Result<Vec<u8>, I2cError> {
+        self.bus.lock().unwrap().try_read_block(device_addr, reg_addr, bytes)
+    }
+}
+
+/// A bus shared between drivers on a single thread, every driver gets its
+/// own [RefCellDevice] and borrows the bus for each block transfer.
+pub struct RefCellBus<T> {
+    bus: RefCell<T>
+}
+
+impl<T: I2c> RefCellBus<T> {
+    pub fn new(bus: T) -> Self {
+        Self { bus: RefCell::new(bus) }
+    }
+    /// Hands out a proxy for a single driver.
+    pub fn proxy(&self) -> RefCellDevice<'_, T> {
+        RefCellDevice { bus: &self.bus }
+    }
+    /// Gives the bus back once every proxy is gone.
+    pub fn into_inner(self) -> T {
+        self.bus.into_inner()
+    }
+}
+
+/// A single driver's view of a [RefCellBus].
+pub struct RefCellDevice<'a, T> {
+    bus: &'a RefCell<T>
+}
+
+impl<T: I2c> I2c for RefCellDevice<'_, T> {
+    fn write_block(&mut self, device_addr: u8, reg_addr: u8, bytes: Vec<u8>) {
+        self.bus.borrow_mut().write_block(device_addr, reg_addr, bytes);
+    }
+    fn read_block(&mut self, device_addr: u8, reg_addr: u8, bytes: u8) -> Vec<u8> {
+        self.bus.borrow_mut().read_block(device_addr, reg_addr, bytes)
+    }
+    fn try_write_block(&mut self, device_addr: u8, reg_addr: u8, bytes: Vec<u8>) -> Result<(), I2cError> {
+        self.bus.borrow_mut().try_write_block(device_addr, reg_addr, bytes)
+    }
+    fn try_read_block(&mut self, device_addr: u8, reg_addr: u8, bytes: u8) -> Result<Vec<u8>, I2cError> {
+        self.bus.borrow_mut().try_read_block(device_addr, reg_addr, bytes)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{core::Register, i2c::{I2CSlave, I2c, Master}};
+
+    use super::{MutexBus, RefCellBus};
+
+    /// A driver that owns its bus handle, like most sensor drivers do.
+    struct Sensor<B> {
+        bus: B,
+        address: u8
+    }
+
+    impl<B: I2c> Sensor<B> {
+        fn configure(&mut self, value: u8) {
+            self.bus.write_block(self.address, 0x12, vec![ value, !value ]);
+        }
+        fn config(&mut self) -> u8 {
+            let config = self.bus.read_block(self.address, 0x12, 2);
+            assert_eq!(config[1], !config[0]);
+            config[0]
+        }
+    }
+
+    fn master() -> Master {
+        let mut master = Master::new();
+        for address in [0x68, 0x32] {
+            let mut slave = I2CSlave::new(address);
+            slave.create_register(0x12, Register::new_writeable());
+            master.add_device(slave);
+        }
+        master
+    }
+
+    #[test]
+    pub fn test_refcell_bus() {
+        let bus = RefCellBus::new(master());
+        let mut imu = Sensor { bus: bus.proxy(), address: 0x68 };
+        let mut baro = Sensor { bus: bus.proxy(), address: 0x32 };
+
+        imu.configure(0x21);
+        baro.configure(0x42);
+        assert_eq!(imu.config(), 0x21);
+        assert_eq!(baro.config(), 0x42);
+    }
+
+    #[test]
+    pub fn test_mutex_bus() {
+        let bus = MutexBus::new(master());
+
+        std::thread::scope(|scope| {
+            for (address, value) in [(0x68, 0x21), (0x32, 0x42)] {
+                let mut sensor = Sensor { bus: bus.proxy(), address };
+                scope.spawn(move || {
+                    for _ in 0..10 {
+                        sensor.configure(value);
+                        assert_eq!(sensor.config(), value);
+                    }
+                });
+            }
+        });
+
+        let mut master = bus.into_inner();
+        assert_eq!(master.read_block(0x32, 0x12, 2), vec![ 0x42, !0x42 ]);
+    }
+}
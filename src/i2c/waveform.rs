@@ -0,0 +1,147 @@
+use std::time::Duration;
+
+use crate::core::LogicTrace;
+
+use super::{I2cEvent, I2cProbe};
+
+/// Samples taken per SCL cycle, SDA changes in the first half while SCL is low
+/// and holds in the second half while SCL is high.
+const SAMPLES_PER_CYCLE: u64 = 4;
+
+/// Turns the events on an [I2CBus](super::I2CBus) back into the SDA and SCL
+/// levels that would have been on the wire.
+///
+/// Attach it with [I2CBus::add_probe](super::I2CBus::add_probe) behind an
+/// `Arc<Mutex<_>>` and export the [LogicTrace] it builds.
+pub struct I2cWaveform {
+    trace: LogicTrace,
+    /// The SCL cycle the first event happened at.
+    origin: Option<u64>,
+    /// The levels the lines are at.
+    scl: bool,
+    sda: bool
+}
+
+impl I2cWaveform {
+    /// The channel of SCL in the trace.
+    pub const SCL: usize = 0;
+    /// The channel of SDA in the trace.
+    pub const SDA: usize = 1;
+
+    /// Creates a waveform for a bus clocked with the given SCL period.
+    pub fn new(scl_period: Duration) -> Self {
+        Self {
+            trace: LogicTrace::new("i2c", &["SCL", "SDA"], scl_period / SAMPLES_PER_CYCLE as u32),
+            origin: None,
+            scl: true,
+            sda: true
+        }
+    }
+    /// The lines so far.
+    pub fn trace(&self) -> &LogicTrace {
+        &self.trace
+    }
+    /// Takes the lines so far and starts over.
+    pub fn take(&mut self) -> LogicTrace {
+        let empty = LogicTrace::new("i2c", &["SCL", "SDA"], self.trace.period());
+        self.origin = None;
+        std::mem::replace(&mut self.trace, empty)
+    }
+    fn push(&mut self, scl: bool, sda: bool) {
+        self.scl = scl;
+        self.sda = sda;
+        self.trace.push((scl as u32) << Self::SCL | (sda as u32) << Self::SDA);
+    }
+    /// A single SCL cycle, `sda` is the level SDA moves to while SCL is low
+    /// and then the level it moves to while SCL is high.
+    fn cycle(&mut self, sda: [bool; 2]) {
+        self.push(false, self.sda);
+        self.push(false, sda[0]);
+        self.push(true, sda[0]);
+        self.push(true, sda[1]);
+    }
+    fn bit(&mut self, bit: bool) {
+        self.cycle([bit, bit]);
+    }
+}
+
+/// A bus clocked at 100 kHz.
+impl Default for I2cWaveform {
+    fn default() -> Self {
+        Self::new(Duration::from_micros(10))
+    }
+}
+
+impl I2cProbe for I2cWaveform {
+    fn on_event(&mut self, event: &I2cEvent, cycle: u64) {
+        // SCL and SDA stay put through the cycles without an event.
+        let origin = *self.origin.get_or_insert(cycle);
+        let expected = (cycle - origin) * SAMPLES_PER_CYCLE;
+        while (self.trace.len() as u64) < expected {
+            self.push(self.scl, self.sda);
+        }
+
+        match *event {
+            I2cEvent::Start if self.scl && self.sda => {
+                // From an idle bus SDA simply falls while SCL stays high.
+                self.push(true, true);
+                self.push(true, true);
+                self.push(true, false);
+                self.push(true, false);
+            }
+            // A repeated start raises SDA while SCL is low, then pulls it low
+            // while SCL is high.
+            I2cEvent::Start => self.cycle([true, false]),
+            I2cEvent::Byte { value, .. } => {
+                for i in (0..8).rev() {
+                    self.bit(value >> i & 1 == 1);
+                }
+            }
+            I2cEvent::Ack { ack, .. } => self.bit(!ack),
+            I2cEvent::Stop => self.cycle([false, true])
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::{Arc, Mutex};
+
+    use crate::{i2c::Master, testing};
+
+    use super::I2cWaveform;
+
+    #[test]
+    pub fn test_waveform() {
+        let mut master = Master::new();
+        master.add_device(testing::i2c_bmi270());
+        let waveform = Arc::new(Mutex::new(I2cWaveform::default()));
+        master.add_probe(waveform.clone());
+
+        master.write_block(0x68, 0x7D, vec![ 0x02, 0x0E ]);
+
+        let waveform = waveform.lock().unwrap();
+        let trace = waveform.trace();
+        // Start, the address, register and two data bytes with their ACKs and a stop.
+        assert_eq!(trace.len(), 4 * (1 + 4 * 9 + 1));
+
+        // SDA only changes while SCL is high for the start and the stop.
+        let conditions: Vec<bool> = (1..trace.len())
+            .filter(|&i| trace.level(i - 1, I2cWaveform::SCL) && trace.level(i, I2cWaveform::SCL))
+            .filter(|&i| trace.level(i - 1, I2cWaveform::SDA) != trace.level(i, I2cWaveform::SDA))
+            .map(|i| trace.level(i, I2cWaveform::SDA))
+            .collect();
+        assert_eq!(conditions, vec![ false, true ]);
+
+        // The address bits are sampled on the rising edges of SCL.
+        let bits: Vec<bool> = (1..trace.len())
+            .filter(|&i| !trace.level(i - 1, I2cWaveform::SCL) && trace.level(i, I2cWaveform::SCL))
+            .map(|i| trace.level(i, I2cWaveform::SDA))
+            .take(9)
+            .collect();
+        let address = bits[..8].iter().fold(0u8, |byte, &bit| byte << 1 | bit as u8);
+        assert_eq!(address, 0x68 << 1);
+        // The slave acknowledged by pulling SDA low.
+        assert!(!bits[8]);
+    }
+}
@@ -3,11 +3,14 @@ pub mod core;
 pub mod i2c;
 pub mod spi;
 
+#[cfg(test)]
+pub(crate) mod testing;
+
 
 #[cfg(test)]
 mod tests {
-    use std::{collections::HashMap, time::Duration};
-    use crate::{core::Register, i2c::{I2CSlave, Master}, spi::{master::{Disconnected, SpiMaster}, slave::SpiSlave}};
+    use std::{collections::HashMap, sync::{Arc, Mutex}, time::Duration};
+    use crate::{core::{DeviceId, Direction, MemorySink, Register, Tracer, Verbosity}, i2c::{I2CSlave, Master}, spi::{device::{SpiContext, SpiDevice}, error::SpiError, wire::Level, framing::{SpiFraming, SpiLanes}, master::{Disconnected, SpiMaster}, slave::SpiSlave, flash::{command, SpiFlash}, probe::{SpiProbe, SpiSample}, transaction::SpiTransaction}, testing};
 
 
     #[test]
@@ -120,8 +123,8 @@ mod tests {
 
         let (master, _) = master.connect(slave, Duration::from_millis(1));
 
-        master.write_register(0x15, vec![ 0x21 ]);
-        assert_eq!(master.read_register(0x15, 1), vec![ 0x21 ]);
+        master.write_register(0x15, vec![ 0x21 ]).unwrap();
+        assert_eq!(master.read_register(0x15, 1).unwrap(), vec![ 0x21 ]);
 
     }
 
@@ -141,7 +144,393 @@ mod tests {
 
         let (master, _) = master.connect(slave, Duration::from_millis(1));
 
-        assert_eq!(master.read_register(0x15, 2), vec![ 0x21, 0x59 ]);
+        assert_eq!(master.read_register(0x15, 2).unwrap(), vec![ 0x21, 0x59 ]);
+
+    }
+
+    #[test]
+    pub fn spi_auto_increment_read() {
+        fn data_x0() -> Vec<u8> {
+            vec![ 0x11 ]
+        }
+
+        fn data_x1() -> Vec<u8> {
+            vec![ 0x22 ]
+        }
+
+        let master = SpiMaster::with_framing(SpiFraming::adxl345());
+        let slave = SpiSlave::with_framing(HashMap::from([
+            (0x32, Register::new_read_only(data_x0)),
+            (0x33, Register::new_read_only(data_x1))
+        ]), SpiFraming::adxl345());
+
+        let (master, _) = master.connect(slave, Duration::from_millis(1));
+
+        assert_eq!(master.read_register(0x32, 2).unwrap(), vec![ 0x11, 0x22 ]);
+        assert_eq!(master.read_register(0x33, 1).unwrap(), vec![ 0x22 ]);
+    }
+
+    #[test]
+    pub fn spi_dummy_byte_read() {
+        let master = SpiMaster::with_framing(SpiFraming::bmi270());
+        let (master, _) = master.connect(testing::spi_bmi270(), Duration::from_millis(1));
+
+        assert_eq!(master.read_register(0x00, 1).unwrap(), vec![ 0x24 ]);
+
+        master.write_register(0x7D, vec![ 0x0E ]).unwrap();
+        assert_eq!(master.read_register(0x7D, 1).unwrap(), vec![ 0x0E ]);
+    }
+
+    #[test]
+    pub fn spi_register_observer() {
+        let seen = Arc::new(Mutex::new(vec![]));
+        let slave = SpiSlave::new(HashMap::from([
+            (0x15, Register::new_writeable().on_write({
+                let seen = seen.clone();
+                move |byte| seen.lock().unwrap().push(byte)
+            }))
+        ]));
+        let mut sim = SpiMaster::new().simulate(slave, Duration::from_micros(1));
+
+        // Every byte of a burst is seen once, in the order it was sent.
+        sim.write_register(0x15, vec![ 0x01, 0x02, 0x03 ]);
+        assert_eq!(*seen.lock().unwrap(), vec![ 0x01, 0x02, 0x03 ]);
+        assert_eq!(sim.read_register(0x15, 3), vec![ 0x01, 0x02, 0x03 ]);
+
+        sim.write_register(0x15, vec![ 0x04 ]);
+        assert_eq!(*seen.lock().unwrap(), vec![ 0x01, 0x02, 0x03, 0x04 ]);
+        assert_eq!(sim.read_register(0x15, 1), vec![ 0x04 ]);
+    }
+
+    #[test]
+    pub fn spi_try_errors() {
+        fn broken() -> Vec<u8> {
+            panic!("The sensor is wedged.");
+        }
+
+        let slave = SpiSlave::new(HashMap::from([
+            (0x15, Register::new_writeable()),
+            (0x16, Register::new_read_only(broken))
+        ]));
+        let (master, _) = SpiMaster::new().connect(slave, Duration::from_millis(1));
+
+        let timeout = Duration::from_secs(5);
+        assert_eq!(master.try_read_register(0x15, 1, timeout), Ok(vec![ 0x00 ]));
+        assert_eq!(master.try_read_register(0x20, 1, timeout), Err(SpiError::UnknownRegister(0x20)));
+        assert_eq!(master.try_write_register(0x15, vec![ 0x01 ], Duration::ZERO), Err(SpiError::Timeout));
+        // The write that timed out was dropped and does not go out later.
+        assert_eq!(master.try_read_register(0x15, 1, timeout), Ok(vec![ 0x00 ]));
+
+        // Reading the broken register takes the slave down.
+        assert_eq!(master.try_read_register(0x16, 1, timeout), Err(SpiError::Disconnected));
+    }
+
+    #[test]
+    pub fn spi_disconnect_and_reconnect() {
+        let slave = SpiSlave::new(HashMap::from([
+            (0x15, Register::new_writeable())
+        ]));
+        let (master, slave) = SpiMaster::new().connect(slave, Duration::from_millis(1));
+        master.write_register(0x15, vec![ 0x42 ]).unwrap();
+
+        let (master, slave) = master.disconnect(slave);
+
+        // The register keeps its value across a reconnect at a new speed.
+        let (master, slave) = master.connect(slave, Duration::from_micros(200));
+        assert_eq!(master.read_register(0x15, 1).unwrap(), vec![ 0x42 ]);
+
+        let (master, slave) = master.disconnect(slave);
+
+        // A clone blocked on a request while the master disconnects gets an
+        // error, and it can not reach the next connection either.
+        let (master, slave) = master.connect(slave, Duration::from_micros(200));
+        let clone = master.clone();
+        let reader = std::thread::spawn(move || loop {
+            if let Err(err) = clone.read_register(0x15, 1) {
+                return (clone, err);
+            }
+        });
+        std::thread::sleep(Duration::from_millis(5));
+        let (master, slave) = master.disconnect(slave);
+        let (clone, err) = reader.join().unwrap();
+        assert_eq!(err, SpiError::Disconnected);
+        let (master, slave) = master.connect(slave, Duration::from_micros(200));
+        assert_eq!(clone.read_register(0x15, 1), Err(SpiError::Disconnected));
+        assert_eq!(clone.write_register(0x15, vec![ 0x00 ]), Err(SpiError::Disconnected));
+        assert_eq!(master.read_register(0x15, 1).unwrap(), vec![ 0x42 ]);
+
+        let (master, slave) = master.disconnect(slave);
+        let mut sim = master.simulate(slave, Duration::from_millis(1));
+        assert_eq!(sim.read_register(0x15, 1), vec![ 0x42 ]);
+
+        // Dropping the last handle of a connection stops both threads.
+        let (master, slave) = sim.finish();
+        let (master, slave) = master.connect(slave, Duration::from_micros(200));
+        let clone = master.clone();
+        drop(master);
+        assert_eq!(clone.read_register(0x15, 1).unwrap(), vec![ 0x42 ]);
+        drop(clone);
+        let slave = slave.join();
+
+        // A slave can only be taken back by its own master.
+        let (other, other_slave) = SpiMaster::new().connect(SpiSlave::new(HashMap::new()), Duration::from_micros(200));
+        let (master, slave) = SpiMaster::new().connect(slave, Duration::from_micros(200));
+        let mismatch = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| master.disconnect(other_slave)));
+        assert!(mismatch.is_err());
+        drop(other);
+        slave.join();
+    }
+
+    #[test]
+    pub fn spi_master_thread_dies() {
+        /// Takes the master thread down once a request goes out.
+        struct Wedged;
+        impl SpiProbe for Wedged {
+            fn on_sample(&mut self, sample: &SpiSample) {
+                assert!(!sample.selected(), "The logic analyzer is wedged.");
+            }
+        }
+
+        let slave = SpiSlave::new(HashMap::from([
+            (0x15, Register::new_writeable())
+        ]));
+        let (master, slave) = SpiMaster::new().connect(slave, Duration::from_micros(200));
+        master.medium().add_probe(Wedged);
+
+        // The request on the wire and everything after it fail instead of waiting forever.
+        assert_eq!(master.read_register(0x15, 1), Err(SpiError::Disconnected));
+        assert_eq!(master.write_register(0x15, vec![ 0x01 ]), Err(SpiError::Disconnected));
+        assert_eq!(master.transfer(vec![ 0x00 ], 1), Err(SpiError::Disconnected));
+
+        // The slave was stopped along with it and the pair can be taken back.
+        let (master, slave) = master.disconnect(slave);
+        let (master, slave) = master.connect(slave, Duration::from_micros(200));
+        master.write_register(0x15, vec![ 0x01 ]).unwrap();
+        assert_eq!(master.read_register(0x15, 1), Ok(vec![ 0x01 ]));
+        master.disconnect(slave);
+    }
 
+    #[test]
+    pub fn spi_dual_and_quad_data_phase() {
+        fn status() -> Vec<u8> {
+            vec![ 0xA5, 0x3C ]
+        }
+
+        for lanes in [SpiLanes::Dual, SpiLanes::Quad] {
+            let framing = SpiFraming {
+                data_lanes: lanes,
+                dummy_bytes: 1,
+                ..SpiFraming::default()
+            };
+            let slave = SpiSlave::with_framing(HashMap::from([
+                (0x05, Register::new_read_only(status)),
+                (0x10, Register::new_writeable())
+            ]), framing);
+            let mut sim = SpiMaster::with_framing(framing).simulate(slave, Duration::from_micros(1));
+
+            assert_eq!(sim.read_register(0x05, 2), vec![ 0xA5, 0x3C ]);
+
+            sim.write_register(0x10, vec![ 0x96 ]);
+            assert_eq!(sim.read_register(0x10, 1), vec![ 0x96 ]);
+
+            // The lines were turned around cleanly between the phases.
+            let medium = sim.medium();
+            for wire in [&medium.mosi, &medium.miso, &medium.io2, &medium.io3] {
+                assert!(wire.contentions().is_empty());
+            }
+        }
+    }
+
+    #[test]
+    pub fn spi_three_wire_switch() {
+        let slave = SpiSlave::new(HashMap::from([
+            (0x00, Register::new_read_only(testing::chip_id)),
+            (0x6B, Register::new_writeable())
+        ])).with_three_wire_bit(0x6B, 0x01).unwrap();
+        let mut sim = SpiMaster::new().simulate(slave, Duration::from_micros(1));
+
+        assert_eq!(sim.read_register(0x00, 1), vec![ 0x24 ]);
+        assert!(!sim.medium().is_three_wire());
+
+        // Setting the spi3 bit in IF_CONF folds MISO into SDIO.
+        sim.write_register(0x6B, vec![ 0x01 ]);
+        assert!(sim.medium().is_three_wire());
+        assert_eq!(sim.read_register(0x00, 1), vec![ 0x24 ]);
+        assert_eq!(sim.read_register(0x6B, 1), vec![ 0x01 ]);
+        assert!(sim.medium().mosi.contentions().is_empty());
+        assert_eq!(sim.medium().miso.level(), Level::High);
+
+        sim.write_register(0x6B, vec![ 0x00 ]);
+        assert!(!sim.medium().is_three_wire());
+        assert_eq!(sim.read_register(0x00, 1), vec![ 0x24 ]);
+        // Only register slaves have such a bit.
+        let flash = SpiSlave::from_device(SpiFlash::new(64 * 1024));
+        assert!(matches!(flash.with_three_wire_bit(0x6B, 0x01), Err(SpiError::Unsupported)));
+    }
+
+    #[test]
+    pub fn spi_word_sizes() {
+        /// A 9-bit display controller, the top bit selects data or command.
+        struct Display(Arc<Mutex<Vec<u32>>>);
+
+        impl SpiDevice for Display {
+            fn word_bits(&self) -> usize {
+                9
+            }
+            fn on_word(&mut self, word: u32, _: &mut SpiContext) {
+                self.0.lock().unwrap().push(word);
+            }
+        }
+
+        /// A 16-bit ADC that clocks out a sample whenever it is read.
+        struct Adc;
+
+        impl SpiDevice for Adc {
+            fn word_bits(&self) -> usize {
+                16
+            }
+            fn on_word(&mut self, _: u32, _: &mut SpiContext) {}
+            fn on_output_empty(&mut self, ctx: &mut SpiContext) {
+                ctx.send_word(0xBEEF);
+            }
+        }
+
+        let nine = SpiFraming { word_bits: 9, ..SpiFraming::default() };
+        let received = Arc::new(Mutex::new(vec![]));
+        let mut sim = SpiMaster::with_framing(nine).simulate(SpiSlave::from_device(Display(received.clone())), Duration::from_micros(1));
+        sim.write_words(vec![ 0x02A, 0x1FF, 0x100 ]);
+        assert_eq!(*received.lock().unwrap(), vec![ 0x02A, 0x1FF, 0x100 ]);
+
+        let sixteen = SpiFraming { word_bits: 16, ..SpiFraming::default() };
+        let mut sim = SpiMaster::with_framing(sixteen).simulate(SpiSlave::from_device(Adc), Duration::from_micros(1));
+        assert_eq!(sim.read_words(2), vec![ 0xBEEF, 0xBEEF ]);
+
+        // Register devices carry two bytes per 16-bit frame.
+        let framing = SpiFraming { rw_bit: 15, ..sixteen };
+        let slave = SpiSlave::with_framing(HashMap::from([
+            (0x10, Register::new_writeable())
+        ]), framing);
+        let mut sim = SpiMaster::with_framing(framing).simulate(slave, Duration::from_micros(1));
+        sim.write_register(0x10, vec![ 0x12 ]);
+        assert_eq!(sim.read_register(0x10, 1), vec![ 0x12 ]);
+    }
+
+    #[test]
+    pub fn spi_transaction_holds_cs() {
+        /// Counts how many times CS gets asserted.
+        struct Selects(usize, bool);
+        impl SpiProbe for Selects {
+            fn on_sample(&mut self, sample: &SpiSample) {
+                if sample.selected() && !self.1 {
+                    self.0 += 1;
+                }
+                self.1 = sample.selected();
+            }
+        }
+
+        let mut flash = vec![0xFF; 64 * 1024];
+        flash[0x20..0x24].copy_from_slice(&[ 0x10, 0x20, 0x30, 0x40 ]);
+        let slave = SpiSlave::from_device(SpiFlash::with_image(flash));
+        let (master, slave) = SpiMaster::new().connect(slave, Duration::from_micros(10));
+        let selects = Arc::new(Mutex::new(Selects(0, false)));
+        master.medium().add_probe(selects.clone());
+
+        // The read keeps streaming across the delay and the second read.
+        let transaction = SpiTransaction::new()
+            .write(vec![ command::READ as u32 ])
+            .write(vec![ 0x00, 0x00, 0x20 ])
+            .read(1)
+            .delay(Duration::from_micros(50))
+            .read(3);
+        assert_eq!(master.execute(&transaction).unwrap(), vec![ vec![ 0x10 ], vec![ 0x20, 0x30, 0x40 ] ]);
+        assert_eq!(selects.lock().unwrap().0, 1);
+
+        master.disconnect(slave);
+    }
+
+    #[test]
+    pub fn spi_shared_master() {
+        let slave = SpiSlave::new(HashMap::from_iter((0x10..0x14).map(|reg| (reg, Register::new_writeable()))));
+        let (master, slave) = SpiMaster::new().connect(slave, Duration::from_micros(5));
+        for reg in 0x10..0x14 {
+            master.write_register(reg, vec![ reg as u8 * 2 ]).unwrap();
+        }
+
+        // Every driver gets its own bytes back, even with reads interleaving.
+        let drivers: Vec<_> = (0x10..0x14).map(|reg| {
+            let master = master.clone();
+            std::thread::spawn(move || {
+                for _ in 0..5 {
+                    assert_eq!(master.read_register(reg, 1).unwrap(), vec![ reg as u8 * 2 ]);
+                }
+                master
+            })
+        }).collect();
+        let clone = drivers.into_iter().map(|driver| driver.join().unwrap()).last().unwrap();
+
+        master.disconnect(slave);
+        assert_eq!(clone.try_read_register(0x10, 1, Duration::from_millis(100)), Err(SpiError::Disconnected));
+    }
+
+    #[test]
+    pub fn spi_several_slaves() {
+        let slaves = (0..3).map(|_| SpiSlave::new(HashMap::from([
+            (0x10, Register::new_writeable())
+        ]))).collect();
+        let (master, slaves) = SpiMaster::new().connect_all(slaves, Duration::from_micros(5));
+
+        // Every slave has its own chip select, the others keep off the lines.
+        for cs in 0..3 {
+            master.chip_select(cs).write_register(0x10, vec![ 0x20 + cs as u8 ]).unwrap();
+        }
+        for cs in (0..3).rev() {
+            assert_eq!(master.chip_select(cs).read_register(0x10, 1).unwrap(), vec![ 0x20 + cs as u8 ]);
+        }
+        assert!(master.medium().miso.contentions().is_empty());
+
+        let (master, slaves) = master.disconnect_all(slaves);
+        let mut sim = master.simulate(slaves.into_iter().nth(2).unwrap(), Duration::from_micros(1));
+        assert_eq!(sim.read_register(0x10, 1), vec![ 0x22 ]);
+    }
+
+    #[test]
+    pub fn trace_events() {
+        let sink = MemorySink::new();
+        let tracer = Tracer::new(sink.clone());
+        tracer.set_verbosity(Verbosity::Warn);
+        tracer.set_device_verbosity(DeviceId::I2c(0x68), Verbosity::Trace);
+
+        let mut master = Master::new();
+        master.set_tracer(tracer.clone());
+        for address in [0x68, 0x32] {
+            let mut slave = I2CSlave::new(address);
+            slave.create_register(0x12, Register::new_writeable());
+            master.add_device(slave);
+        }
+        master.write_block(0x68, 0x12, vec![ 0x23, 0x48 ]);
+        master.read_block(0x68, 0x12, 2);
+
+        let events = sink.take();
+        // The other device stays quiet at the default verbosity.
+        assert!(events.iter().all(|e| e.device == Some(DeviceId::I2c(0x68))));
+        let sent: Vec<_> = events.iter()
+            .filter(|e| e.register == Some(0x12))
+            .filter_map(|e| e.value)
+            .collect();
+        // The master sends a block last byte first.
+        assert_eq!(sent, vec![
+            (0x48, Direction::MasterToSlave),
+            (0x23, Direction::MasterToSlave),
+            (0x23, Direction::SlaveToMaster),
+            (0x48, Direction::SlaveToMaster)
+        ]);
+
+        // SPI devices report through the same tracer.
+        let slave = SpiSlave::new(HashMap::new()).with_tracer(&tracer, 1);
+        let mut sim = SpiMaster::new().simulate(slave, Duration::from_micros(1));
+        let _ = sim.read_register(0x40, 1);
+        let events = sink.take();
+        assert_eq!(events.len(), 1);
+        assert_eq!((events[0].device, events[0].verbosity, events[0].register), (Some(DeviceId::Spi(1)), Verbosity::Warn, Some(0x40)));
     }
-}
\ No newline at end of file
+}
@@ -12,13 +12,13 @@ fn main() {
     let master = SpiMaster::new();
     let slave = SpiSlave::new(HashMap::from([(0xF, Register::new_writeable())]));
 
-    let (master, _slave): (SpiMaster<Connected>, SpiSlave<Connected>) =
+    let (master, slave): (SpiMaster<Connected>, SpiSlave<Connected>) =
         master.connect(slave, Duration::from_millis(5));
 
-    master.write_register(0xF, vec![0x21]);
+    master.write_register(0xF, vec![0x21]).unwrap();
 
-    assert_eq!(master.read_register(0xF, 1), vec![ 0x21 ]);
+    assert_eq!(master.read_register(0xF, 1).unwrap(), vec![ 0x21 ]);
 
 
-    master.disconnect();
+    master.disconnect(slave);
 }
@@ -1,35 +1,154 @@
-use rsevents::{AutoResetEvent, Awaitable};
+use std::sync::{Arc, Condvar, Mutex, MutexGuard, PoisonError};
+
 use super::wire::LiveWire;
 
 /// The clock used for SPI communications
+///
+/// Every attached [ClockListener] observes every edge exactly once. The
+/// clock will not produce the next edge until all the listeners are done
+/// with the current one, so a slow slave holds the clock back instead of
+/// missing edges.
 pub struct Clock {
-    /// Thread wakeup mechanism.
-    mechanism: AutoResetEvent,
+    /// The edge bookkeeping shared with the listeners.
+    shared: Arc<ClockShared>,
     /// The clock wire.
     line: LiveWire
 }
 
+struct ClockShared {
+    state: Mutex<ClockState>,
+    /// Signalled when a new edge is produced.
+    edge_signal: Condvar,
+    /// Signalled when a listener is done with an edge.
+    ack_signal: Condvar
+}
+
+impl ClockShared {
+    /// Locks the state, a probe that panicked while an edge was settling
+    /// leaves it poisoned but still consistent, since nothing moved yet.
+    fn lock(&self) -> MutexGuard<'_, ClockState> {
+        self.state.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+}
+
+struct ClockState {
+    /// The amount of edges produced so far.
+    edge: u64,
+    /// The line level after the latest edge.
+    level: bool,
+    /// The amount of attached listeners.
+    listeners: usize,
+    /// The listeners that still have to finish the latest edge.
+    pending: usize
+}
+
+/// A subscription to the edges of a [Clock].
+pub struct ClockListener {
+    shared: Arc<ClockShared>,
+    /// The latest edge this listener took.
+    seen: u64,
+    /// If the listener still has to acknowledge the latest edge it took.
+    holding: bool
+}
+
 impl Clock {
     /// Creates a new clock.
     pub fn new() -> Self {
         Self {
-            mechanism: AutoResetEvent::new(rsevents::EventState::Unset),
+            shared: Arc::new(ClockShared {
+                state: Mutex::new(ClockState {
+                    edge: 0,
+                    level: false,
+                    listeners: 0,
+                    pending: 0
+                }),
+                edge_signal: Condvar::new(),
+                ack_signal: Condvar::new()
+            }),
             line: LiveWire::new()
         }
     }
+    /// Attaches a listener, it will observe every edge from now on.
+    pub fn attach(&self) -> ClockListener {
+        let mut state = self.shared.lock();
+        state.listeners += 1;
+        ClockListener {
+            shared: self.shared.clone(),
+            seen: state.edge,
+            holding: false
+        }
+    }
     /// Gets the line value without waiting.
     pub fn get_line_value(&self) -> bool {
         self.line.read()
     }
-    /// Gets the clock value but waits for it to be update.
-    pub fn get_clock(&self) -> bool {
-        self.mechanism.wait();
-        self.get_line_value()
+    /// The amount of edges produced so far.
+    pub fn edges(&self) -> u64 {
+        self.shared.lock().edge
     }
     /// Ticks the clock.
+    ///
+    /// This first waits for every listener to finish the previous edge.
     pub fn tick(&self) {
-        self.line.flip();
-        self.mechanism.set();
+        self.tick_with(|_, _| {});
+    }
+    /// Ticks the clock, calling `settled` with the latest edge and the line
+    /// level once every listener is done with it and before the line flips.
+    pub fn tick_with(&self, settled: impl FnOnce(u64, bool)) {
+        self.advance(true, settled);
+    }
+    /// Produces an edge for the listeners without moving the line, this is
+    /// how a gated clock idles. `settled` is called like in [Clock::tick_with].
+    pub fn idle_with(&self, settled: impl FnOnce(u64, bool)) {
+        self.advance(false, settled);
+    }
+    fn advance(&self, flip: bool, settled: impl FnOnce(u64, bool)) {
+        let mut state = self.shared.lock();
+        while state.pending > 0 {
+            state = self.shared.ack_signal.wait(state).unwrap_or_else(PoisonError::into_inner);
+        }
+        settled(state.edge, state.level);
+        if flip {
+            self.line.flip();
+        }
+        state.level = self.line.read();
+        state.edge += 1;
+        state.pending = state.listeners;
+        self.shared.edge_signal.notify_all();
+    }
+}
+
+impl ClockListener {
+    /// Waits for the next edge and returns the clock level after it.
+    ///
+    /// Calling this acknowledges the edge returned by the previous call.
+    pub fn get_clock(&mut self) -> bool {
+        let shared = self.shared.clone();
+        let mut state = shared.lock();
+        self.release(&mut state);
+        while state.edge == self.seen {
+            state = shared.edge_signal.wait(state).unwrap_or_else(PoisonError::into_inner);
+        }
+        self.seen = state.edge;
+        self.holding = true;
+        state.level
+    }
+    /// Acknowledges the latest edge if it is still being held.
+    fn release(&mut self, state: &mut ClockState) {
+        if self.holding && self.seen == state.edge {
+            state.pending -= 1;
+            self.shared.ack_signal.notify_all();
+        }
+        self.holding = false;
+    }
+}
+
+impl Drop for ClockListener {
+    fn drop(&mut self) {
+        let shared = self.shared.clone();
+        let mut state = shared.lock();
+        self.release(&mut state);
+        state.listeners -= 1;
     }
 }
 
@@ -37,4 +156,43 @@ impl Default for Clock {
     fn default() -> Self {
         Self::new()
     }
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{sync::Arc, thread::sleep, time::Duration};
+
+    use super::Clock;
+
+    #[test]
+    pub fn test_every_listener_sees_every_edge() {
+        let clock = Arc::new(Clock::new());
+
+        let listeners = [Duration::ZERO, Duration::from_millis(1)].map(|delay| {
+            let mut listener = clock.attach();
+            std::thread::spawn(move || {
+                let mut rising = 0;
+                let mut previous = false;
+                for _ in 0..100 {
+                    let level = listener.get_clock();
+                    if level && !previous {
+                        rising += 1;
+                    }
+                    previous = level;
+                    // A slow listener holds the clock back instead of missing edges.
+                    sleep(delay);
+                }
+                rising
+            })
+        });
+
+        for _ in 0..100 {
+            clock.tick();
+        }
+
+        for listener in listeners {
+            assert_eq!(listener.join().unwrap(), 50);
+        }
+        assert_eq!(clock.edges(), 100);
+    }
+}
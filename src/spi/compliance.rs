@@ -0,0 +1,201 @@
+use super::{probe::{SpiProbe, SpiSample}, wire::Driver};
+
+/// The timing a [ComplianceChecker] holds the link to.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct TimingRules {
+    /// The clock cycles a word takes, chip select may only be released
+    /// on a word boundary. A byte on quad lanes takes 2.
+    pub word_cycles: u64,
+    /// The clock edges needed between asserting chip select and the first rising edge.
+    pub setup_edges: u64,
+    /// The clock edges needed between the last rising edge and releasing chip select.
+    pub hold_edges: u64,
+    /// Expect the clock to stop while chip select is released, the emulated
+    /// master holds it low.
+    pub gated_clock: bool
+}
+
+impl Default for TimingRules {
+    fn default() -> Self {
+        Self {
+            word_cycles: 8,
+            setup_edges: 1,
+            hold_edges: 1,
+            gated_clock: true
+        }
+    }
+}
+
+/// A breach of the [TimingRules] or of the SPI protocol, along with the
+/// clock edge it happened at.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Violation {
+    /// Chip select was released after `cycles` cycles, which is not a whole word.
+    MidWordDeselect { edge: u64, cycles: u64 },
+    /// The clock ran while chip select was released.
+    ClockWhileDeselected { edge: u64 },
+    /// The first rising edge came `edges` edges after chip select was asserted.
+    Setup { edge: u64, edges: u64 },
+    /// Chip select was released `edges` edges after the last rising edge.
+    Hold { edge: u64, edges: u64 },
+    /// A data line changed on the edge it is sampled on.
+    DataChange { edge: u64, line: &'static str }
+}
+
+/// Watches a link and collects every [Violation], attach it to a
+/// [SpiMedium](super::wire::SpiMedium) behind an `Arc<Mutex<_>>` to read it out.
+///
+/// The master drives its lines while the clock is low and the slave samples
+/// them on the rising edge, the slave drives on the rising edge and the master
+/// samples after the falling edge.
+pub struct ComplianceChecker {
+    rules: TimingRules,
+    violations: Vec<Violation>,
+    previous: Option<SpiSample>,
+    /// The edge chip select was asserted at.
+    selected_at: Option<u64>,
+    /// The latest rising edge while selected.
+    last_rising: Option<u64>,
+    /// The rising edges since chip select was asserted.
+    cycles: u64
+}
+
+impl ComplianceChecker {
+    pub fn new(rules: TimingRules) -> Self {
+        Self {
+            rules,
+            violations: vec![],
+            previous: None,
+            selected_at: None,
+            last_rising: None,
+            cycles: 0
+        }
+    }
+    /// Every violation seen so far.
+    pub fn violations(&self) -> &[Violation] {
+        &self.violations
+    }
+    /// Takes the violations seen so far.
+    pub fn take_violations(&mut self) -> Vec<Violation> {
+        std::mem::take(&mut self.violations)
+    }
+    fn check_data(&mut self, previous: &SpiSample, sample: &SpiSample) {
+        // Lines driven by the master are sampled on the rising edge, lines
+        // driven by the slave on the falling edge.
+        let sampled = |driver: Option<Driver>| driver.is_some_and(|d| d.is_slave() != sample.clock);
+        for ((line, before), (_, after)) in previous.lines().into_iter().zip(sample.lines()) {
+            if sampled(before.driver) && sampled(after.driver) && before.level != after.level {
+                self.violations.push(Violation::DataChange { edge: sample.edge, line });
+            }
+        }
+    }
+}
+
+impl Default for ComplianceChecker {
+    fn default() -> Self {
+        Self::new(TimingRules::default())
+    }
+}
+
+impl SpiProbe for ComplianceChecker {
+    fn on_sample(&mut self, sample: &SpiSample) {
+        let edge = sample.edge;
+        let was_selected = self.previous.is_some_and(|p| p.selected());
+
+        if let Some(previous) = self.previous
+            && previous.clock != sample.clock
+        {
+            if previous.selected() && sample.selected() {
+                self.check_data(&previous, sample);
+            }
+            if self.rules.gated_clock && !previous.selected() && !sample.selected() {
+                self.violations.push(Violation::ClockWhileDeselected { edge });
+            }
+        }
+
+        match (was_selected, sample.selected()) {
+            (false, true) => {
+                self.selected_at = Some(edge);
+                self.last_rising = None;
+                self.cycles = 0;
+            }
+            (true, true) if sample.clock && self.previous.is_some_and(|p| !p.clock) => {
+                if self.last_rising.is_none()
+                    && let Some(selected_at) = self.selected_at
+                    && edge - selected_at < self.rules.setup_edges
+                {
+                    self.violations.push(Violation::Setup { edge, edges: edge - selected_at });
+                }
+                self.last_rising = Some(edge);
+                self.cycles += 1;
+            }
+            (true, false) => {
+                if !self.cycles.is_multiple_of(self.rules.word_cycles) {
+                    self.violations.push(Violation::MidWordDeselect { edge, cycles: self.cycles });
+                }
+                if let Some(last_rising) = self.last_rising
+                    && edge - last_rising < self.rules.hold_edges
+                {
+                    self.violations.push(Violation::Hold { edge, edges: edge - last_rising });
+                }
+                self.selected_at = None;
+            }
+            _ => {}
+        }
+        self.previous = Some(*sample);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{collections::HashMap, sync::{Arc, Mutex}, time::Duration};
+
+    use crate::{core::Register, spi::{master::SpiMaster, slave::SpiSlave, wire::SpiMedium}};
+
+    use super::{ComplianceChecker, TimingRules, Violation};
+
+    #[test]
+    pub fn test_master_is_compliant() {
+        let slave = SpiSlave::new(HashMap::from([
+            (0x15, Register::new_writeable())
+        ]));
+        let mut sim = SpiMaster::new().simulate(slave, Duration::from_micros(1));
+        let checker = Arc::new(Mutex::new(ComplianceChecker::default()));
+        sim.medium().add_probe(checker.clone());
+
+        sim.write_register(0x15, vec![ 0x5A ]);
+        assert_eq!(sim.read_register(0x15, 1), vec![ 0x5A ]);
+        sim.run(8);
+        assert_eq!(checker.lock().unwrap().violations(), &[]);
+    }
+
+    #[test]
+    pub fn test_sloppy_driver() {
+        let medium = SpiMedium::new();
+        let checker = Arc::new(Mutex::new(ComplianceChecker::new(TimingRules { setup_edges: 2, ..Default::default() })));
+        medium.add_probe(checker.clone());
+
+        // The clock runs before chip select is asserted.
+        medium.tick();
+        medium.tick();
+        // Chip select goes low right before the rising edge.
+        medium.cs_select.pull(false);
+        medium.mosi.pull(true);
+        medium.tick();
+        // MOSI changes while the clock is high, right on the rising edge.
+        medium.mosi.pull(false);
+        medium.tick();
+        medium.tick();
+        medium.tick();
+        // Chip select goes away after two bits.
+        medium.cs_select.pull(true);
+        medium.tick();
+
+        assert_eq!(checker.lock().unwrap().take_violations(), vec![
+            Violation::ClockWhileDeselected { edge: 1 },
+            Violation::DataChange { edge: 3, line: "MOSI" },
+            Violation::Setup { edge: 3, edges: 1 },
+            Violation::MidWordDeselect { edge: 6, cycles: 2 }
+        ]);
+    }
+}
@@ -0,0 +1,154 @@
+use crate::core::LogicTrace;
+
+use super::{framing::{SpiFraming, SpiFramingError}, record::SpiRecord, waveform::SpiWaveform};
+
+/// Something on the lines that does not make up a valid SPI frame.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SpiFrameError {
+    /// Chip select was released after only `bits` bits of the last frame.
+    PartialWord { at: usize, bits: usize },
+    /// The trace ended while chip select was still asserted.
+    Unterminated { at: usize }
+}
+
+/// What [SpiDecoder::decode] made of a trace: one record per chip select
+/// assertion and the frames that broke the protocol.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct SpiDecoded {
+    /// The transactions, `start` and `end` are sample indices of the trace.
+    pub records: Vec<SpiRecord>,
+    pub errors: Vec<SpiFrameError>
+}
+
+/// Rebuilds single lane SPI transactions from the levels of a [LogicTrace],
+/// sampling MOSI and MISO on the rising edges of SCLK.
+///
+/// Only modes 0 and 3 are decoded, captures of modes 1 and 2 sample on the
+/// falling edge and come out shifted by half a clock.
+#[derive(Clone, Debug)]
+pub struct SpiDecoder {
+    framing: SpiFraming,
+    /// The channels of SCLK, MOSI, MISO and CS.
+    channels: [usize; 4]
+}
+
+impl SpiDecoder {
+    /// Creates a decoder for the channels an [SpiWaveform] writes, failing
+    /// if the framing can not be used.
+    pub fn new(framing: SpiFraming) -> Result<Self, SpiFramingError> {
+        framing.validate()?;
+        Ok(Self {
+            framing,
+            channels: [SpiWaveform::SCLK, SpiWaveform::MOSI, SpiWaveform::MISO, SpiWaveform::CS]
+        })
+    }
+    /// Reads the lines from other channels, e.g. the ones of an imported capture.
+    pub fn with_channels(mut self, sclk: usize, mosi: usize, miso: usize, cs: usize) -> Self {
+        self.channels = [sclk, mosi, miso, cs];
+        self
+    }
+    pub fn decode(&self, trace: &LogicTrace) -> SpiDecoded {
+        let [sclk, mosi, miso, cs] = self.channels;
+        let mut decoded = SpiDecoded::default();
+        let mut current: Option<SpiRecord> = None;
+        let mut bits = 0;
+
+        for at in 0..trace.len() {
+            let selected = !trace.level(at, cs);
+            if !selected {
+                if let Some(mut record) = current.take() {
+                    if bits != 0 {
+                        decoded.errors.push(SpiFrameError::PartialWord { at, bits });
+                        record.mosi.pop();
+                        record.miso.pop();
+                        bits = 0;
+                    }
+                    record.end = at as u64;
+                    record.decode(&self.framing);
+                    decoded.records.push(record);
+                }
+                continue;
+            }
+            let record = current.get_or_insert_with(|| SpiRecord {
+                start: at as u64,
+                end: at as u64,
+                mosi: vec![],
+                miso: vec![],
+                command: None,
+                data: vec![]
+            });
+            // A clock already high when chip select falls is not an edge.
+            let rising = at > 0 && !trace.level(at - 1, sclk) && trace.level(at, sclk)
+                && !trace.level(at - 1, cs);
+            if !rising {
+                continue;
+            }
+            if bits == 0 {
+                record.mosi.push(0);
+                record.miso.push(0);
+            }
+            *record.mosi.last_mut().unwrap() = record.mosi.last().unwrap() << 1 | trace.level(at, mosi) as u32;
+            *record.miso.last_mut().unwrap() = record.miso.last().unwrap() << 1 | trace.level(at, miso) as u32;
+            bits = (bits + 1) % self.framing.word_bits;
+        }
+        if current.is_some() {
+            decoded.errors.push(SpiFrameError::Unterminated { at: trace.len() });
+        }
+        decoded
+    }
+}
+
+impl Default for SpiDecoder {
+    fn default() -> Self {
+        Self::new(SpiFraming::default()).unwrap()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{sync::{Arc, Mutex}, time::Duration};
+
+    use crate::{core::LogicTrace, spi::{framing::SpiFraming, waveform::SpiWaveform}, testing};
+
+    use super::{SpiDecoder, SpiFrameError, SpiFramingError};
+
+    #[test]
+    pub fn test_decode_waveform() {
+        let (mut sim, recorder) = testing::spi_recorded(testing::spi_bmi270());
+        let waveform = Arc::new(Mutex::new(SpiWaveform::new(Duration::from_micros(1))));
+        sim.medium().add_probe(waveform.clone());
+
+        sim.write_register(0x7D, vec![ 0x0E ]);
+        sim.read_register(0x7D, 1);
+        sim.run(4);
+
+        let decoded = SpiDecoder::new(SpiFraming::bmi270()).unwrap().decode(waveform.lock().unwrap().trace());
+        assert!(decoded.errors.is_empty());
+        let recorded = recorder.lock().unwrap().take();
+        let frames = |records: &[crate::spi::record::SpiRecord]| records.iter()
+            .map(|r| (r.command, r.mosi.clone(), r.data.clone()))
+            .collect::<Vec<_>>();
+        assert_eq!(frames(&decoded.records), frames(&recorded));
+    }
+
+    #[test]
+    pub fn test_partial_word() {
+        // Three bits are clocked before chip select goes away, then it falls again.
+        let mut trace = LogicTrace::new("spi", &["SCLK", "MOSI", "MISO", "CS"], Duration::from_micros(1));
+        for sample in [0b1000, 0b0000, 0b0011, 0b0000, 0b0001, 0b0000, 0b0011, 0b1000, 0b0000] {
+            trace.push(sample);
+        }
+
+        let decoded = SpiDecoder::default().decode(&trace);
+        assert_eq!(decoded.errors, vec![
+            SpiFrameError::PartialWord { at: 7, bits: 3 },
+            SpiFrameError::Unterminated { at: 9 }
+        ]);
+        assert_eq!(decoded.records.len(), 1);
+        assert!(decoded.records[0].mosi.is_empty());
+
+        // A framing that can not be decoded is refused up front.
+        let framing = SpiFraming { word_bits: 0, ..SpiFraming::default() };
+        assert_eq!(SpiDecoder::new(framing).unwrap_err(), SpiFramingError::WordBits(0));
+    }
+}
@@ -0,0 +1,236 @@
+use std::{any::Any, collections::HashMap};
+
+use crate::core::{DeviceTracer, Direction, Port, Register, TraceEvent, Verbosity};
+
+use super::{error::SpiError, framing::{SpiFraming, SpiLanes}, wire::SpiMedium};
+
+/// The logic of a device sitting behind a [SpiSlave](super::slave::SpiSlave).
+///
+/// The slave takes care of the wires, the device only deals in frames
+/// of [SpiDevice::word_bits] bits.
+pub trait SpiDevice: Any + Send {
+    /// The width of a frame in bits, between 4 and 32.
+    fn word_bits(&self) -> usize {
+        8
+    }
+    /// Handles a frame clocked in from the master.
+    fn on_word(&mut self, word: u32, ctx: &mut SpiContext);
+    /// Called when the output has run dry while the device is selected,
+    /// queue more frames to keep talking or leave it empty to listen again.
+    fn on_output_empty(&mut self, _ctx: &mut SpiContext) {}
+    /// Called when chip select is released.
+    fn on_deselect(&mut self) {}
+    /// Hands the device a tracer to report what it is doing through.
+    fn set_tracer(&mut self, _tracer: DeviceTracer) {}
+}
+
+/// What a [SpiDevice] can do to the link while handling a frame.
+pub struct SpiContext<'a> {
+    pub(crate) medium: &'a SpiMedium,
+    pub(crate) output: &'a mut Port,
+    pub(crate) lanes: &'a mut SpiLanes,
+    pub(crate) word_bits: usize
+}
+
+impl SpiContext<'_> {
+    /// Queues a frame to be sent to the master.
+    pub fn send_word(&mut self, word: u32) {
+        self.output.write_word(word, self.word_bits);
+    }
+    /// Queues a byte to be sent to the master, regardless of the frame width.
+    pub fn send_byte(&mut self, byte: u8) {
+        self.output.write_byte(byte);
+    }
+    /// The amount of bits still waiting to go out.
+    pub fn pending_bits(&self) -> usize {
+        self.output.bits_read()
+    }
+    /// Switches the lanes used from now on, this goes back to a single
+    /// lane once chip select is released.
+    pub fn set_lanes(&mut self, lanes: SpiLanes) {
+        *self.lanes = lanes;
+    }
+    /// The amount of clock cycles the link has run for, devices use this
+    /// to model how long internal operations take.
+    pub fn cycles(&self) -> u64 {
+        self.medium.clock.edges() / 2
+    }
+    /// Reports a fault to the master.
+    pub fn report(&self, fault: SpiError) {
+        self.medium.report(fault);
+    }
+    /// Switches the medium between 4-wire and 3-wire mode.
+    pub fn set_three_wire(&self, enabled: bool) {
+        self.medium.set_three_wire(enabled);
+    }
+}
+
+/// A device made out of addressable registers, accessed with a command
+/// header laid out as described by a [SpiFraming].
+pub struct RegisterDevice {
+    registers: HashMap<u32, Register>,
+    framing: SpiFraming,
+    /// The register and bit mask that switch the device into 3-wire mode.
+    three_wire_bit: Option<(u32, u8)>,
+    state: RegisterState,
+    trace: DeviceTracer
+}
+
+enum RegisterState {
+    /// Collecting the frames of the command header.
+    Command(Vec<u32>),
+    /// Writing frames into a register.
+    Writing {
+        register: u32,
+        increment: bool,
+        /// If a frame went into the register yet.
+        started: bool
+    },
+    /// Streaming a register out over MISO.
+    Reading {
+        register: u32,
+        increment: bool
+    }
+}
+
+impl RegisterDevice {
+    pub fn new(registers: HashMap<u32, Register>, framing: SpiFraming) -> Self {
+        if let Err(error) = framing.validate() {
+            panic!("{}", error);
+        }
+        Self {
+            registers,
+            framing,
+            three_wire_bit: None,
+            state: RegisterState::Command(vec![]),
+            trace: DeviceTracer::default()
+        }
+    }
+    /// Lets the device switch the medium into 3-wire mode when the bits in
+    /// `mask` are written to `register`, like the `spi3` bit in the BMI270's
+    /// IF_CONF register. Clearing them switches back to 4-wire mode.
+    pub fn with_three_wire_bit(mut self, register: u32, mask: u8) -> Self {
+        self.set_three_wire_bit(register, mask);
+        self
+    }
+    /// Sets the register bit that switches the medium into 3-wire mode.
+    pub(crate) fn set_three_wire_bit(&mut self, register: u32, mask: u8) {
+        self.three_wire_bit = Some((register, mask));
+    }
+    /// Loads the contents of a register into the output.
+    fn load_register(&mut self, register: u32, ctx: &mut SpiContext) {
+        if let Some(register) = self.registers.get_mut(&register) {
+            let mut bytes = vec![];
+            register.start_read();
+            while !register.is_done() {
+                bytes.push(register.read_byte().unwrap());
+            }
+            register.finish_read();
+            for word in self.framing.pack(&bytes) {
+                ctx.send_word(word);
+            }
+        } else {
+            self.trace.warn(TraceEvent::new("No such register exists.").register(register));
+            ctx.report(SpiError::UnknownRegister(register));
+            ctx.send_word(0x00);
+        }
+    }
+}
+
+impl SpiDevice for RegisterDevice {
+    fn word_bits(&self) -> usize {
+        self.framing.word_bits
+    }
+    fn on_word(&mut self, value: u32, ctx: &mut SpiContext) {
+        if self.trace.enabled(Verbosity::Trace) {
+            self.trace.trace(TraceEvent::new(format!("Word read {:#x}.", value)).value(value, Direction::MasterToSlave));
+        }
+
+        match &mut self.state {
+            RegisterState::Command(header) => {
+                header.push(value);
+                if header.len() < self.framing.header_words {
+                    // The header is not complete yet.
+                    return;
+                }
+                let command = self.framing.decode(header);
+                let register = command.register;
+                ctx.set_lanes(self.framing.data_lanes);
+                if command.read {
+                    // Read call.
+                    if self.trace.enabled(Verbosity::Debug) {
+                        self.trace.debug(TraceEvent::new(format!("Read call for register [{:#x}].", register)).register(register));
+                    }
+                    for _ in 0..self.framing.dummy_bytes {
+                        ctx.send_byte(0x00);
+                    }
+                    self.load_register(register, ctx);
+                    self.state = RegisterState::Reading { register, increment: command.increment };
+                } else {
+                    if self.trace.enabled(Verbosity::Debug) {
+                        self.trace.debug(TraceEvent::new(format!("Write call for register [{:#x}].", register)).register(register));
+                    }
+                    if !command.increment && !self.registers.contains_key(&register) {
+                        self.trace.warn(TraceEvent::new("No such register exists.").register(register));
+                        ctx.report(SpiError::UnknownRegister(register));
+                    }
+                    self.state = RegisterState::Writing { register, increment: command.increment, started: false };
+                }
+            }
+            RegisterState::Writing { register, increment, started } => {
+                if self.trace.enabled(Verbosity::Debug) {
+                    self.trace.debug(TraceEvent::new(format!("Writing word {:#x} to register [{:#x}].", value, *register))
+                        .register(*register)
+                        .value(value, Direction::MasterToSlave));
+                }
+                let bytes = self.framing.unpack(&[value], self.framing.word_bytes());
+                if let Some(rgstr) = self.registers.get_mut(register) {
+                    // A burst to a single register keeps adding to it.
+                    if *increment || !*started {
+                        rgstr.start_write();
+                    }
+                    *started = true;
+                    for byte in &bytes {
+                        rgstr.append_byte(*byte);
+                    }
+                    let last = *bytes.last().unwrap();
+                    if let Some((switch, mask)) = self.three_wire_bit
+                        && switch == *register
+                    {
+                        self.trace.info(TraceEvent::new(format!("Switching to {}-wire mode.", if last & mask == mask { 3 } else { 4 })));
+                        ctx.set_three_wire(last & mask == mask);
+                    }
+                } else if *increment {
+                    self.trace.warn(TraceEvent::new("No such register exists.").register(*register));
+                    ctx.report(SpiError::UnknownRegister(*register));
+                }
+                if *increment {
+                    *register += 1;
+                }
+            }
+            RegisterState::Reading { .. } => {
+                // The master is just clocking, nothing to do.
+            }
+        }
+    }
+    fn on_output_empty(&mut self, ctx: &mut SpiContext) {
+        // Keeps the output going while the master is still clocking out a read.
+        if let RegisterState::Reading { register, increment } = &mut self.state {
+            if *increment {
+                // Move on to the next register.
+                *register += 1;
+                let register = *register;
+                self.load_register(register, ctx);
+            } else {
+                // Past the end of the register we just send 0x00.
+                ctx.send_word(0x00);
+            }
+        }
+    }
+    fn on_deselect(&mut self) {
+        self.state = RegisterState::Command(vec![]);
+    }
+    fn set_tracer(&mut self, tracer: DeviceTracer) {
+        self.trace = tracer;
+    }
+}
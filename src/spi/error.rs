@@ -0,0 +1,35 @@
+use std::fmt;
+
+/// The errors a SPI operation can end in.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum SpiError {
+    /// The operation did not complete within the timeout.
+    Timeout,
+    /// The other end of the link is no longer running.
+    Disconnected,
+    /// Fewer bytes came back than were requested.
+    ShortRead {
+        /// The amount of bytes requested.
+        expected: usize,
+        /// The amount of bytes that arrived.
+        received: usize
+    },
+    /// The slave reported that it has no such register.
+    UnknownRegister(u32),
+    /// The device behind the slave does not support what was asked of it.
+    Unsupported
+}
+
+impl fmt::Display for SpiError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Timeout => write!(f, "the SPI operation timed out"),
+            Self::Disconnected => write!(f, "the SPI link is disconnected"),
+            Self::ShortRead { expected, received } => write!(f, "expected {expected} bytes but only {received} arrived"),
+            Self::UnknownRegister(register) => write!(f, "the slave has no register {register:#x}"),
+            Self::Unsupported => write!(f, "the device behind the slave does not support this")
+        }
+    }
+}
+
+impl std::error::Error for SpiError {}
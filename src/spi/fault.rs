@@ -0,0 +1,105 @@
+use crate::core::FaultPlan;
+
+use super::wire::SpiMedium;
+
+/// A fault a [SpiMedium] can inject, see [SpiMedium::set_faults].
+///
+/// Faults fire on clock cycles, counted from zero over every cycle with chip
+/// select asserted.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SpiFault {
+    /// Flips the bit on MOSI before the slave samples it.
+    FlipMosi,
+    /// Flips the bit on MISO before the master samples it.
+    CorruptMiso,
+    /// The slave misses the rising edge of the cycle.
+    DropEdge,
+    /// The slave drops off the link for good: it lets go of its lines and
+    /// ignores the clock.
+    Vanish
+}
+
+/// The faults of a [SpiMedium] and where the link is at.
+pub(crate) struct SpiFaults {
+    pub(crate) plan: FaultPlan<SpiFault>,
+    /// The cycles with chip select asserted so far.
+    cycle: u64,
+    /// The faults firing on the current cycle.
+    pub(crate) current: Vec<SpiFault>,
+    /// If the slave vanished.
+    pub(crate) vanished: bool
+}
+
+impl SpiFaults {
+    pub(crate) fn new(plan: FaultPlan<SpiFault>) -> Self {
+        Self { plan, cycle: 0, current: vec![], vanished: false }
+    }
+    /// Called with the settled wires before the clock flips from `clock`.
+    pub(crate) fn inject(&mut self, medium: &SpiMedium, clock: bool) {
+        if clock {
+            // The master samples MISO after the falling edge.
+            medium.mosi.force(None);
+            if self.current.contains(&SpiFault::CorruptMiso) {
+                medium.miso.force(Some(!medium.miso.read()));
+            }
+            return;
+        }
+        // The slave samples MOSI on the rising edge, which starts a cycle.
+        medium.miso.force(None);
+        self.current.clear();
+        if !medium.is_selected() {
+            return;
+        }
+        self.current = self.plan.roll(self.cycle);
+        self.cycle += 1;
+        self.vanished |= self.current.contains(&SpiFault::Vanish);
+        if self.current.contains(&SpiFault::FlipMosi) {
+            medium.mosi.force(Some(!medium.mosi.read()));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use crate::{core::{FaultPlan, Trigger}, spi::{framing::SpiFraming, master::SpiMaster, sim::SpiSimulation}, testing};
+
+    use super::SpiFault;
+
+    fn link() -> SpiSimulation {
+        SpiMaster::with_framing(SpiFraming::bmi270()).simulate(testing::spi_bmi270(), Duration::from_micros(1))
+    }
+
+    #[test]
+    pub fn test_faults() {
+        // The header and the dummy byte take up the first 16 cycles of a read.
+        let mut sim = link();
+        sim.medium().set_faults(FaultPlan::new().with(SpiFault::CorruptMiso, Trigger::Nth(16)));
+        assert_eq!(sim.read_register(0x00, 1), vec![ 0xA4 ]);
+        assert_eq!(sim.read_register(0x00, 1), vec![ 0x24 ]);
+
+        // A missed edge shifts everything the slave sends by a bit.
+        let mut sim = link();
+        sim.medium().set_faults(FaultPlan::new().with(SpiFault::DropEdge, Trigger::Nth(1)));
+        assert_eq!(sim.read_register(0x00, 1), vec![ 0x12 ]);
+
+        // A slave that is gone leaves MISO to the pull-up.
+        let mut sim = link();
+        sim.medium().set_faults(FaultPlan::new().with(SpiFault::Vanish, Trigger::Nth(19)));
+        assert_eq!(sim.read_register(0x00, 1), vec![ 0x3F ]);
+        assert_eq!(sim.read_register(0x00, 1), vec![ 0xFF ]);
+
+        // Random faults repeat with the same seed.
+        let run = |seed: u64| {
+            let mut sim = link();
+            sim.medium().set_faults(FaultPlan::seeded(seed).with(SpiFault::FlipMosi, Trigger::Chance(0.02)));
+            let reads: Vec<Vec<u8>> = (0..20).map(|_| sim.read_register(0x00, 1)).collect();
+            (reads, sim.medium().fired_faults())
+        };
+        let (reads, fired) = run(3);
+        assert!(!fired.is_empty());
+        assert!(reads.contains(&vec![ 0x24 ]));
+        assert_eq!(run(3), (reads, fired));
+    }
+}
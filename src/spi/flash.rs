@@ -0,0 +1,361 @@
+use crate::core::{DeviceTracer, TraceEvent, Verbosity};
+
+use super::device::{SpiContext, SpiDevice};
+
+/// Status register 1, busy with a program or erase.
+pub const STATUS_WIP: u8 = 0x01;
+/// Status register 1, writes are enabled.
+pub const STATUS_WEL: u8 = 0x02;
+
+/// The size of a programmable page.
+pub const PAGE_SIZE: usize = 256;
+/// The size of the smallest erasable sector.
+pub const SECTOR_SIZE: usize = 4 * 1024;
+
+/// How long internal operations keep the WIP bit set, in clock cycles.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct FlashTiming {
+    pub page_program: u64,
+    pub sector_erase: u64,
+    pub block_erase: u64,
+    pub chip_erase: u64
+}
+
+impl Default for FlashTiming {
+    fn default() -> Self {
+        Self {
+            page_program: 64,
+            sector_erase: 256,
+            block_erase: 512,
+            chip_erase: 1024
+        }
+    }
+}
+
+/// The commands understood by [SpiFlash].
+pub mod command {
+    pub const WRITE_STATUS: u8 = 0x01;
+    pub const PAGE_PROGRAM: u8 = 0x02;
+    pub const READ: u8 = 0x03;
+    pub const WRITE_DISABLE: u8 = 0x04;
+    pub const READ_STATUS_1: u8 = 0x05;
+    pub const WRITE_ENABLE: u8 = 0x06;
+    pub const FAST_READ: u8 = 0x0B;
+    pub const SECTOR_ERASE: u8 = 0x20;
+    pub const READ_STATUS_2: u8 = 0x35;
+    pub const BLOCK_ERASE_32K: u8 = 0x52;
+    pub const CHIP_ERASE: u8 = 0xC7;
+    pub const CHIP_ERASE_ALT: u8 = 0x60;
+    pub const READ_JEDEC_ID: u8 = 0x9F;
+    pub const BLOCK_ERASE_64K: u8 = 0xD8;
+}
+
+/// A JEDEC SPI NOR flash in the style of the Winbond W25Q series.
+///
+/// Programs and erases are carried out when chip select is released and keep
+/// the WIP bit set for the time given by the [FlashTiming]. Commands other than
+/// the status reads are ignored while the chip is busy.
+pub struct SpiFlash {
+    memory: Vec<u8>,
+    jedec_id: [u8; 3],
+    /// Status registers 1 and 2.
+    status: [u8; 2],
+    timing: FlashTiming,
+    /// The cycle at which the current internal operation finishes.
+    busy_until: u64,
+    /// The latest cycle count seen on the link.
+    now: u64,
+    state: FlashState,
+    trace: DeviceTracer
+}
+
+enum FlashState {
+    /// Waiting for the command byte.
+    Opcode,
+    /// Collecting the 24-bit address of a command.
+    Address {
+        opcode: u8,
+        address: u32,
+        remaining: usize
+    },
+    /// Swallowing the dummy byte of a fast read.
+    Dummy {
+        address: u32
+    },
+    /// Streaming memory out.
+    Reading {
+        address: u32
+    },
+    /// Collecting bytes for a page program.
+    Programming {
+        address: u32,
+        data: Vec<u8>
+    },
+    /// An erase waiting for chip select to be released.
+    Erase {
+        opcode: u8,
+        address: u32
+    },
+    /// Collecting the new status register values.
+    WritingStatus(Vec<u8>),
+    /// Streaming out a fixed response over and over.
+    Responding {
+        response: Vec<u8>,
+        index: usize
+    },
+    /// Ignoring everything until chip select is released.
+    Ignore
+}
+
+impl SpiFlash {
+    /// Creates an erased flash, the capacity must be a power of two of at
+    /// least 64 KiB and determines the capacity byte of the JEDEC ID.
+    pub fn new(capacity: usize) -> Self {
+        assert!(capacity.is_power_of_two() && capacity >= 64 * 1024, "The capacity must be a power of two of at least 64 KiB.");
+        Self::with_image(vec![0xFF; capacity])
+    }
+    /// Creates a flash holding the given image.
+    pub fn with_image(image: Vec<u8>) -> Self {
+        assert!(image.len().is_power_of_two() && image.len() >= 64 * 1024, "The capacity must be a power of two of at least 64 KiB.");
+        Self {
+            jedec_id: [0xEF, 0x40, image.len().trailing_zeros() as u8],
+            memory: image,
+            status: [0x00; 2],
+            timing: FlashTiming::default(),
+            busy_until: 0,
+            now: 0,
+            state: FlashState::Opcode,
+            trace: DeviceTracer::default()
+        }
+    }
+    /// Sets how long programs and erases take.
+    pub fn with_timing(mut self, timing: FlashTiming) -> Self {
+        self.timing = timing;
+        self
+    }
+    /// The contents of the flash.
+    pub fn memory(&self) -> &[u8] {
+        &self.memory
+    }
+    fn is_busy(&self) -> bool {
+        self.now < self.busy_until
+    }
+    fn status_1(&self) -> u8 {
+        let mut status = self.status[0] & !STATUS_WIP;
+        if self.is_busy() {
+            status |= STATUS_WIP;
+        }
+        status
+    }
+    /// Starts an internal operation, this clears the WEL bit like the real chip.
+    fn start_operation(&mut self, cycles: u64) {
+        self.busy_until = self.now + cycles;
+        self.status[0] &= !STATUS_WEL;
+    }
+    fn erase(&mut self, address: u32, size: usize, cycles: u64) {
+        let start = (address as usize % self.memory.len()) & !(size - 1);
+        if self.trace.enabled(Verbosity::Debug) {
+            self.trace.debug(TraceEvent::new(format!("Erasing {} bytes at {:#x}.", size, start)));
+        }
+        self.memory[start..start + size].fill(0xFF);
+        self.start_operation(cycles);
+    }
+    fn on_opcode(&mut self, opcode: u8) {
+        use command::*;
+
+        if self.is_busy() && opcode != READ_STATUS_1 && opcode != READ_STATUS_2 {
+            self.trace.warn(TraceEvent::new(format!("Busy, ignoring command {:#x}.", opcode)));
+            self.state = FlashState::Ignore;
+            return;
+        }
+
+        self.state = match opcode {
+            WRITE_ENABLE => {
+                self.status[0] |= STATUS_WEL;
+                FlashState::Ignore
+            }
+            WRITE_DISABLE => {
+                self.status[0] &= !STATUS_WEL;
+                FlashState::Ignore
+            }
+            READ_STATUS_1 => FlashState::Responding { response: vec![], index: 0 },
+            READ_STATUS_2 => FlashState::Responding { response: vec![self.status[1]], index: 0 },
+            READ_JEDEC_ID => FlashState::Responding { response: self.jedec_id.to_vec(), index: 0 },
+            WRITE_STATUS => FlashState::WritingStatus(vec![]),
+            CHIP_ERASE | CHIP_ERASE_ALT => FlashState::Erase { opcode, address: 0 },
+            READ | FAST_READ | PAGE_PROGRAM | SECTOR_ERASE | BLOCK_ERASE_32K | BLOCK_ERASE_64K => FlashState::Address {
+                opcode,
+                address: 0,
+                remaining: 3
+            },
+            _ => {
+                self.trace.warn(TraceEvent::new(format!("Unknown command {:#x}.", opcode)));
+                FlashState::Ignore
+            }
+        };
+    }
+    /// The next byte to send while responding.
+    fn next_response(&mut self) -> Option<u8> {
+        let status = self.status_1();
+        match &mut self.state {
+            FlashState::Reading { address } => {
+                let byte = self.memory[*address as usize];
+                *address = (*address + 1) % self.memory.len() as u32;
+                Some(byte)
+            }
+            // Status register 1 is sent over and over so the master can poll WIP.
+            FlashState::Responding { response, .. } if response.is_empty() => Some(status),
+            FlashState::Responding { response, index } => {
+                let byte = response.get(*index).copied().unwrap_or(0x00);
+                *index += 1;
+                Some(byte)
+            }
+            _ => None
+        }
+    }
+}
+
+impl SpiDevice for SpiFlash {
+    fn on_word(&mut self, word: u32, ctx: &mut SpiContext) {
+        self.now = ctx.cycles();
+        let byte = word as u8;
+
+        match &mut self.state {
+            FlashState::Opcode => self.on_opcode(byte),
+            FlashState::Address { opcode, address, remaining } => {
+                *address = (*address << 8) | byte as u32;
+                *remaining -= 1;
+                if *remaining == 0 {
+                    let opcode = *opcode;
+                    let address = *address % self.memory.len() as u32;
+                    self.state = match opcode {
+                        command::READ => FlashState::Reading { address },
+                        command::FAST_READ => FlashState::Dummy { address },
+                        command::PAGE_PROGRAM => FlashState::Programming { address, data: vec![] },
+                        _ => FlashState::Erase { opcode, address }
+                    };
+                }
+            }
+            FlashState::Dummy { address } => {
+                self.state = FlashState::Reading { address: *address };
+            }
+            FlashState::Programming { data, .. } => data.push(byte),
+            FlashState::WritingStatus(values) => values.push(byte),
+            _ => {}
+        }
+    }
+    fn on_output_empty(&mut self, ctx: &mut SpiContext) {
+        self.now = ctx.cycles();
+        if let Some(byte) = self.next_response() {
+            ctx.send_byte(byte);
+        }
+    }
+    fn on_deselect(&mut self) {
+        let state = std::mem::replace(&mut self.state, FlashState::Opcode);
+        let write_enabled = self.status[0] & STATUS_WEL != 0;
+
+        match state {
+            FlashState::Programming { address, data } if write_enabled => {
+                // Writes past the end of the page wrap around to its start,
+                // only the last page worth of bytes is kept.
+                let page = address as usize & !(PAGE_SIZE - 1);
+                let skipped = data.len().saturating_sub(PAGE_SIZE);
+                let offset = (address as usize + skipped) % PAGE_SIZE;
+                if self.trace.enabled(Verbosity::Debug) {
+                    self.trace.debug(TraceEvent::new(format!("Programming {} bytes at {:#x}.", data.len(), address)));
+                }
+                for (i, byte) in data[skipped..].iter().enumerate() {
+                    // Programming can only clear bits, erasing sets them again.
+                    self.memory[page + (offset + i) % PAGE_SIZE] &= byte;
+                }
+                self.start_operation(self.timing.page_program);
+            }
+            FlashState::Erase { opcode, address } if write_enabled => match opcode {
+                command::SECTOR_ERASE => self.erase(address, SECTOR_SIZE, self.timing.sector_erase),
+                command::BLOCK_ERASE_32K => self.erase(address, 32 * 1024, self.timing.block_erase),
+                command::BLOCK_ERASE_64K => self.erase(address, 64 * 1024, self.timing.block_erase),
+                _ => {
+                    let size = self.memory.len();
+                    self.erase(0, size, self.timing.chip_erase);
+                }
+            },
+            FlashState::WritingStatus(values) if write_enabled && !values.is_empty() => {
+                // WIP and WEL are read only.
+                self.status[0] = (values[0] & !(STATUS_WIP | STATUS_WEL)) | (self.status[0] & STATUS_WEL);
+                if let Some(value) = values.get(1) {
+                    self.status[1] = *value;
+                }
+                self.status[0] &= !STATUS_WEL;
+            }
+            FlashState::Programming { .. } | FlashState::Erase { .. } | FlashState::WritingStatus(_) => {
+                self.trace.warn(TraceEvent::new("Write enable latch not set, ignoring the command."));
+            }
+            _ => {}
+        }
+    }
+    fn set_tracer(&mut self, tracer: DeviceTracer) {
+        self.trace = tracer;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use crate::spi::{master::SpiMaster, sim::SpiSimulation, slave::SpiSlave};
+
+    use super::{command, SpiFlash, STATUS_WEL, STATUS_WIP};
+
+    fn read(sim: &mut SpiSimulation, address: u32, len: usize) -> Vec<u32> {
+        sim.transfer(vec![command::READ as u32, address >> 16 & 0xFF, address >> 8 & 0xFF, address & 0xFF], len)
+    }
+
+    fn wait_ready(sim: &mut SpiSimulation) -> usize {
+        let mut polls = 0;
+        while sim.transfer(vec![command::READ_STATUS_1 as u32], 1)[0] as u8 & STATUS_WIP != 0 {
+            polls += 1;
+        }
+        polls
+    }
+
+    #[test]
+    pub fn test_flash_program_and_erase() {
+        let slave = SpiSlave::from_device(SpiFlash::new(64 * 1024));
+        let mut sim = SpiMaster::new().simulate(slave, Duration::from_micros(1));
+
+        assert_eq!(sim.transfer(vec![command::READ_JEDEC_ID as u32], 3), vec![0xEF, 0x40, 0x10]);
+
+        // Programming without the write enable latch does nothing.
+        sim.write_words(vec![command::PAGE_PROGRAM as u32, 0x00, 0x10, 0xFE, 0x12]);
+        assert_eq!(read(&mut sim, 0x10FE, 2), vec![0xFF, 0xFF]);
+
+        sim.write_words(vec![command::WRITE_ENABLE as u32]);
+        assert_eq!(sim.transfer(vec![command::READ_STATUS_1 as u32], 1)[0] as u8, STATUS_WEL);
+
+        // The third byte wraps around to the start of the page.
+        sim.write_words(vec![command::PAGE_PROGRAM as u32, 0x00, 0x10, 0xFE, 0x12, 0x34, 0x56]);
+        assert!(wait_ready(&mut sim) > 0);
+        assert_eq!(read(&mut sim, 0x10FE, 2), vec![0x12, 0x34]);
+        assert_eq!(read(&mut sim, 0x1000, 1), vec![0x56]);
+        assert_eq!(sim.transfer(vec![command::READ_STATUS_1 as u32], 1)[0] as u8, 0x00);
+
+        // Erasing the sector brings it back to 0xFF.
+        sim.write_words(vec![command::WRITE_ENABLE as u32]);
+        sim.write_words(vec![command::SECTOR_ERASE as u32, 0x00, 0x10, 0x80]);
+        wait_ready(&mut sim);
+        assert_eq!(read(&mut sim, 0x10FE, 2), vec![0xFF, 0xFF]);
+        assert_eq!(sim.transfer(vec![command::FAST_READ as u32, 0x00, 0x10, 0x00, 0x00], 1), vec![0xFF]);
+
+        // Sending more than a page keeps only the last 256 bytes.
+        sim.write_words(vec![command::WRITE_ENABLE as u32]);
+        let mut program = vec![command::PAGE_PROGRAM as u32, 0x00, 0x10, 0xFE];
+        program.extend((0..300).map(|i| i / 2));
+        sim.write_words(program);
+        wait_ready(&mut sim);
+        let mut page = vec![0; 256];
+        for i in 44..300 {
+            page[(0xFE + i) % 256] = i as u32 / 2;
+        }
+        assert_eq!(read(&mut sim, 0x1000, 256), page);
+    }
+}
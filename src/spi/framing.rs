@@ -0,0 +1,251 @@
+use std::fmt;
+
+/// Describes how the command header of a register access is laid out
+/// on the SPI wire.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct SpiFraming {
+    /// The width of a frame in bits, between 4 and 32.
+    pub word_bits: usize,
+    /// The amount of frames making up the command header (R/W bit, flags and address).
+    pub header_words: usize,
+    /// The position of the R/W bit inside the header, counted from the LSB.
+    pub rw_bit: u32,
+    /// If a set R/W bit denotes a read (`true`) or a write (`false`).
+    pub read_high: bool,
+    /// The position of the auto-increment bit (ADXL345's MB bit), if any.
+    pub auto_increment_bit: Option<u32>,
+    /// The amount of dummy bytes the slave sends before the read data.
+    pub dummy_bytes: usize,
+    /// The lanes the data phase runs on, the command header always
+    /// goes out on a single lane.
+    pub data_lanes: SpiLanes,
+}
+
+/// The amount of data lines a transfer phase uses.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Default)]
+pub enum SpiLanes {
+    /// Standard SPI over MOSI and MISO.
+    #[default]
+    Single,
+    /// Two bits per clock over IO0 and IO1.
+    Dual,
+    /// Four bits per clock over IO0 to IO3.
+    Quad
+}
+
+impl SpiLanes {
+    /// The amount of bits moved per clock.
+    pub fn width(self) -> usize {
+        match self {
+            Self::Single => 1,
+            Self::Dual => 2,
+            Self::Quad => 4
+        }
+    }
+}
+
+/// Why a [SpiFraming] can not be used.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SpiFramingError {
+    /// Frames have to be between 4 and 32 bits wide.
+    WordBits(usize),
+    /// The command header does not fit into 64 bits, or is empty.
+    HeaderBits(usize),
+    /// A flag bit lies outside of the command header.
+    FlagBit(u32)
+}
+
+impl fmt::Display for SpiFramingError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::WordBits(bits) => write!(f, "frames must be between 4 and 32 bits wide, not {bits}"),
+            Self::HeaderBits(bits) => write!(f, "the command header must be between 1 and 64 bits long, not {bits}"),
+            Self::FlagBit(bit) => write!(f, "bit {bit} lies outside of the command header")
+        }
+    }
+}
+
+impl std::error::Error for SpiFramingError {}
+
+/// A decoded command header.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct SpiCommand {
+    /// The register being accessed.
+    pub register: u32,
+    /// If this is a read.
+    pub read: bool,
+    /// If the address should advance after every register.
+    pub increment: bool,
+}
+
+impl SpiFraming {
+    /// Creates a framing with the given frame width, header length and R/W
+    /// bit, checking that they fit together.
+    pub fn new(word_bits: usize, header_words: usize, rw_bit: u32, read_high: bool) -> Result<Self, SpiFramingError> {
+        let framing = Self { word_bits, header_words, rw_bit, read_high, ..Self::default() };
+        framing.validate()?;
+        Ok(framing)
+    }
+    /// Checks that the frames are 4 to 32 bits wide, that the command header
+    /// fits into 64 bits and that the R/W and auto-increment bits lie inside it.
+    pub fn validate(&self) -> Result<(), SpiFramingError> {
+        if !(4..=32).contains(&self.word_bits) {
+            return Err(SpiFramingError::WordBits(self.word_bits));
+        }
+        let header_bits = self.header_words * self.word_bits;
+        if !(1..=64).contains(&header_bits) {
+            return Err(SpiFramingError::HeaderBits(header_bits));
+        }
+        for bit in std::iter::once(self.rw_bit).chain(self.auto_increment_bit) {
+            if bit as usize >= header_bits {
+                return Err(SpiFramingError::FlagBit(bit));
+            }
+        }
+        Ok(())
+    }
+    /// The framing used by the ADXL345, where bit 6 is the MB bit.
+    pub fn adxl345() -> Self {
+        Self {
+            auto_increment_bit: Some(6),
+            ..Self::default()
+        }
+    }
+    /// The framing used by the BMI270, which sends a dummy byte on reads.
+    pub fn bmi270() -> Self {
+        Self {
+            dummy_bytes: 1,
+            ..Self::default()
+        }
+    }
+    /// The amount of register bytes carried by a single frame.
+    pub fn word_bytes(&self) -> usize {
+        self.word_bits.div_ceil(8)
+    }
+    /// Masks a value down to the frame width.
+    pub fn mask(&self, word: u32) -> u32 {
+        if self.word_bits >= 32 {
+            word
+        } else {
+            word & ((1 << self.word_bits) - 1)
+        }
+    }
+    /// Packs register bytes into frames, [SpiFraming::word_bytes] big endian bytes at a time.
+    pub fn pack(&self, bytes: &[u8]) -> Vec<u32> {
+        bytes
+            .chunks(self.word_bytes())
+            .map(|chunk| {
+                // A short chunk at the end is padded with zero bytes.
+                let word = chunk.iter().fold(0u32, |acc, b| (acc << 8) | *b as u32);
+                self.mask(word << (8 * (self.word_bytes() - chunk.len())))
+            })
+            .collect()
+    }
+    /// Unpacks frames back into `bytes` register bytes.
+    pub fn unpack(&self, words: &[u32], bytes: usize) -> Vec<u8> {
+        let mut result: Vec<u8> = words
+            .iter()
+            .flat_map(|word| word.to_be_bytes()[4 - self.word_bytes()..].to_vec())
+            .collect();
+        result.truncate(bytes);
+        result
+    }
+    /// Encodes a command header into the frames that go out on the wire.
+    pub fn encode(&self, register: u32, read: bool, increment: bool) -> Vec<u32> {
+        // Bits beyond the header are dropped, see [SpiFraming::validate].
+        let bit = |bit: u32| 1u64.checked_shl(bit).unwrap_or(0);
+        let mut header = register as u64;
+        if read == self.read_high {
+            header |= bit(self.rw_bit);
+        }
+        if let Some(increment_bit) = self.auto_increment_bit
+            && increment
+        {
+            header |= bit(increment_bit);
+        }
+        (0..self.header_words)
+            .rev()
+            .map(|i| self.mask(header.checked_shr((i * self.word_bits) as u32).unwrap_or(0) as u32))
+            .collect()
+    }
+    /// Decodes a command header received from the wire.
+    pub fn decode(&self, header: &[u32]) -> SpiCommand {
+        let bit = |bit: u32| 1u64.checked_shl(bit).unwrap_or(0);
+        let mut value = header.iter().fold(0u64, |acc, w| acc.checked_shl(self.word_bits as u32).unwrap_or(0) | *w as u64);
+
+        let read = (value & bit(self.rw_bit) != 0) == self.read_high;
+        value &= !bit(self.rw_bit);
+
+        let mut increment = false;
+        if let Some(increment_bit) = self.auto_increment_bit {
+            increment = value & bit(increment_bit) != 0;
+            value &= !bit(increment_bit);
+        }
+
+        SpiCommand {
+            register: value as u32,
+            read,
+            increment,
+        }
+    }
+}
+
+impl Default for SpiFraming {
+    /// A single command byte with bit 7 set on reads.
+    fn default() -> Self {
+        Self {
+            word_bits: 8,
+            header_words: 1,
+            rw_bit: 7,
+            read_high: true,
+            auto_increment_bit: None,
+            dummy_bytes: 0,
+            data_lanes: SpiLanes::Single,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{SpiCommand, SpiFraming, SpiFramingError};
+
+    #[test]
+    pub fn test_framing_roundtrip() {
+        let framing = SpiFraming::adxl345();
+        let header = framing.encode(0x32, true, true);
+        assert_eq!(header, vec![0xF2]);
+        assert_eq!(
+            framing.decode(&header),
+            SpiCommand {
+                register: 0x32,
+                read: true,
+                increment: true
+            }
+        );
+
+        let framing = SpiFraming {
+            header_words: 2,
+            rw_bit: 0,
+            read_high: false,
+            ..SpiFraming::default()
+        };
+        let header = framing.encode(0x1234 << 1, false, false);
+        assert_eq!(header, vec![0x24, 0x69]);
+        assert!(!framing.decode(&header).read);
+        assert_eq!(framing.decode(&header).register, 0x1234 << 1);
+
+        let framing = SpiFraming {
+            word_bits: 16,
+            ..framing
+        };
+        assert_eq!(framing.pack(&[0x12, 0x34, 0x56]), vec![0x1234, 0x5600]);
+        assert_eq!(framing.unpack(&[0x1234, 0x5600], 3), vec![0x12, 0x34, 0x56]);
+
+        assert_eq!(SpiFraming::new(16, 2, 15, true).map(|f| f.header_words), Ok(2));
+        assert_eq!(SpiFraming::new(32, 3, 7, true), Err(SpiFramingError::HeaderBits(96)));
+        assert_eq!(SpiFraming::new(8, 1, 64, true), Err(SpiFramingError::FlagBit(64)));
+        assert_eq!(SpiFraming::new(2, 1, 0, true), Err(SpiFramingError::WordBits(2)));
+        // Framings built by hand never panic on the wire.
+        let wide = SpiFraming { word_bits: 32, header_words: 3, rw_bit: 70, ..SpiFraming::default() };
+        assert_eq!(wide.encode(0x12, true, false), vec![0x00, 0x00, 0x12]);
+    }
+}
@@ -0,0 +1,184 @@
+use std::{collections::VecDeque, fmt::{self, Display}, fs, io, path::Path};
+
+use crate::core::GoldenMismatch;
+
+use super::{
+    device::{SpiContext, SpiDevice},
+    framing::SpiFraming,
+    master::Disconnected,
+    record::SpiRecord,
+    slave::SpiSlave,
+};
+
+/// A recorded SPI session to check later runs against.
+///
+/// The session is stored one transaction per line, every frame written as
+/// `[ MOSI ]( MISO )`. It can be replayed by a device answering exactly as
+/// recorded, or used to check that a driver still sends the same frames.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct SpiGolden {
+    framing: SpiFraming,
+    records: Vec<SpiRecord>
+}
+
+impl SpiGolden {
+    /// Creates a golden trace from recorded transactions, e.g. the ones of a
+    /// [SpiRecorder](super::record::SpiRecorder) using the same framing.
+    pub fn new(records: Vec<SpiRecord>, framing: SpiFraming) -> Self {
+        Self { framing, records }
+    }
+    pub fn records(&self) -> &[SpiRecord] {
+        &self.records
+    }
+    /// Parses a golden trace, the framing is needed to tell reads from writes.
+    pub fn parse(text: &str, framing: SpiFraming) -> io::Result<Self> {
+        let invalid = |line: usize, message: &str| io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("line {}: {}", line + 1, message)
+        );
+        let mut records = vec![];
+        for (index, line) in text.lines().enumerate() {
+            let mut rest = line.split('#').next().unwrap_or_default().trim();
+            if rest.is_empty() {
+                continue;
+            }
+            let mut record = SpiRecord { start: 0, end: 0, mosi: vec![], miso: vec![], command: None, data: vec![] };
+            while !rest.is_empty() {
+                let (mosi, tail) = rest.strip_prefix('[').and_then(|r| r.split_once(']'))
+                    .ok_or_else(|| invalid(index, "expected [ MOSI ]"))?;
+                let (miso, tail) = tail.trim_start().strip_prefix('(').and_then(|r| r.split_once(')'))
+                    .ok_or_else(|| invalid(index, "expected ( MISO ) after every [ MOSI ]"))?;
+                let word = |value: &str| {
+                    let value = value.trim();
+                    value.strip_prefix("0x").map_or_else(|| value.parse().ok(), |hex| u32::from_str_radix(hex, 16).ok())
+                        .ok_or_else(|| invalid(index, "expected a frame in hex or decimal"))
+                };
+                record.mosi.push(word(mosi)?);
+                record.miso.push(word(miso)?);
+                rest = tail.trim_start();
+            }
+            record.decode(&framing);
+            records.push(record);
+        }
+        Ok(Self { framing, records })
+    }
+    /// Writes the trace to a file.
+    pub fn save(&self, path: impl AsRef<Path>) -> io::Result<()> {
+        fs::write(path, self.to_string())
+    }
+    /// Reads a trace from a file.
+    pub fn load(path: impl AsRef<Path>, framing: SpiFraming) -> io::Result<Self> {
+        Self::parse(&fs::read_to_string(path)?, framing)
+    }
+
+    /// A device that answers every read exactly as recorded, one transaction
+    /// after the other. Writes are dropped.
+    pub fn device(&self) -> GoldenDevice {
+        let header = self.framing.header_words;
+        GoldenDevice {
+            framing: self.framing,
+            responses: self.records.iter()
+                .map(|r| match r.command {
+                    Some(command) if command.read => r.miso.iter().skip(header).copied().collect(),
+                    _ => vec![]
+                })
+                .collect(),
+            words: 0,
+            selected: false
+        }
+    }
+    /// A slave around [SpiGolden::device].
+    pub fn slave(&self) -> SpiSlave<Disconnected> {
+        SpiSlave::from_device(self.device())
+    }
+    /// Checks that a run sent the same frames on MOSI as the golden trace.
+    pub fn verify(&self, records: &[SpiRecord]) -> Result<(), GoldenMismatch> {
+        GoldenMismatch::compare(&self.records, records, |r| r.mosi.clone(), |r| r.to_string())
+    }
+}
+
+impl Display for SpiGolden {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for record in &self.records {
+            writeln!(f, "{}", record)?;
+        }
+        Ok(())
+    }
+}
+
+/// Plays back the answers of a [SpiGolden].
+pub struct GoldenDevice {
+    framing: SpiFraming,
+    /// What to send after the header, for every transaction still to come.
+    responses: VecDeque<Vec<u32>>,
+    /// The frames received in the current transaction.
+    words: usize,
+    /// If the device was clocked since the last deselect.
+    selected: bool
+}
+
+impl SpiDevice for GoldenDevice {
+    fn word_bits(&self) -> usize {
+        self.framing.word_bits
+    }
+    fn on_word(&mut self, _word: u32, ctx: &mut SpiContext) {
+        self.selected = true;
+        self.words += 1;
+        // Answer at the same point the recorded device did, right after the header.
+        if self.words == self.framing.header_words
+            && let Some(response) = self.responses.front()
+        {
+            for &word in response {
+                ctx.send_word(word);
+            }
+        }
+    }
+    fn on_output_empty(&mut self, _ctx: &mut SpiContext) {
+        self.selected = true;
+    }
+    fn on_deselect(&mut self) {
+        // The slave also resets the device when it connects.
+        if self.selected {
+            self.responses.pop_front();
+        }
+        self.selected = false;
+        self.words = 0;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use crate::{spi::{framing::SpiFraming, master::Disconnected, record::{SpiRecord, SpiRecorder}, slave::SpiSlave}, testing};
+
+    use super::SpiGolden;
+
+    fn run(slave: SpiSlave<Disconnected>, power: u8) -> (Vec<u8>, SpiRecorder) {
+        let (mut sim, recorder) = testing::spi_recorded(slave);
+        sim.write_register(0x7D, vec![ power ]);
+        let id = sim.read_register(0x00, 1);
+        sim.run(4);
+        drop(sim);
+        (id, Arc::into_inner(recorder).unwrap().into_inner().unwrap())
+    }
+
+    #[test]
+    pub fn test_golden() {
+        let (id, mut recorder) = run(testing::spi_bmi270(), 0x0E);
+        assert_eq!(id, vec![ 0x24 ]);
+        let golden = SpiGolden::new(recorder.take(), SpiFraming::bmi270());
+        let golden = SpiGolden::parse(&golden.to_string(), SpiFraming::bmi270()).unwrap();
+
+        // The replayed device answers like the real one.
+        let (id, recorder) = run(golden.slave(), 0x0E);
+        assert_eq!(id, vec![ 0x24 ]);
+        assert_eq!(golden.verify(recorder.records()), Ok(()));
+        assert_eq!(golden.records(), recorder.records().iter()
+            .map(|r| SpiRecord { start: 0, end: 0, ..r.clone() })
+            .collect::<Vec<_>>());
+
+        let (_, recorder) = run(golden.slave(), 0x02);
+        assert_eq!(golden.verify(recorder.records()).unwrap_err().index, 0);
+    }
+}
@@ -1,5 +1,5 @@
 use std::{
-    collections::VecDeque, marker::PhantomData, sync::{atomic::{AtomicBool, Ordering}, Arc, Mutex}, thread::sleep, time::Duration
+    collections::VecDeque, marker::PhantomData, sync::{atomic::{AtomicBool, AtomicUsize, Ordering}, Arc, Mutex}, thread::{sleep, JoinHandle}, time::Duration
 };
 
 use rsevents::{AutoResetEvent, Awaitable, EventState};
@@ -7,9 +7,12 @@ use rsevents::{AutoResetEvent, Awaitable, EventState};
 use crate::core::Port;
 
 use super::{
-    clock::Clock,
+    error::SpiError,
+    framing::{SpiFraming, SpiLanes},
+    sim::SpiSimulation,
     slave::SpiSlave,
-    wire::{LiveWire, SpiMedium},
+    transaction::{SpiOperation, SpiTransaction},
+    wire::{Driver, SpiMedium},
 };
 
 
@@ -25,129 +28,582 @@ pub struct SpiMaster<S> {
     inner: Arc<SpiMasterInner>,
     /// The phantom type that allows us to use
     /// the type state pattern.
-    _type: PhantomData<S>
+    _type: PhantomData<S>,
+    /// The connection, shared between clones while connected.
+    link: Option<Arc<Link>>,
+    /// The chip select line requests go to.
+    cs: usize
+}
+
+/// A connection of a master to a slave.
+///
+/// Once the last handle to a connection is dropped without disconnecting,
+/// the master thread is stopped, which also stops the slave thread.
+struct Link {
+    inner: Arc<SpiMasterInner>,
+    /// The thread driving the clock, until the link is torn down.
+    thread: Mutex<Option<JoinHandle<()>>>,
+    /// The wires shared with the slave.
+    medium: Arc<SpiMedium>
+}
+
+impl Link {
+    /// Stops the master thread and waits for it, returns `false` if the
+    /// link was already torn down.
+    fn stop(&self) -> bool {
+        let Some(thread) = self.thread.lock().unwrap().take() else {
+            return false;
+        };
+        self.inner.kill_switch.store(true, Ordering::SeqCst);
+        // A panic in the master thread has already been reported.
+        let _ = thread.join();
+        true
+    }
+}
+
+impl Drop for Link {
+    fn drop(&mut self) {
+        self.stop();
+    }
 }
 
 /// The inner struct that stores the master state which is necessary for communication.
-struct SpiMasterInner {
+pub(crate) struct SpiMasterInner {
     /// The instructions being sent, this allows things to be sent
     /// in an ordered manner.
     instruction: Mutex<VecDeque<InstrVar>>,
-    /// Bytes being read from the wire.
-    read_buf: Mutex<Port>,
     /// A signal to kill the inner thread.
-    kill_switch: AtomicBool
+    kill_switch: AtomicBool,
+    /// Set once the inner thread has exited, nothing runs the queue then.
+    stopped: AtomicBool,
+    /// How commands are framed on the wire.
+    framing: SpiFraming
 }
 
 /// The internal instructions being sent to the SPI port.
 enum InstrVar {
     /// Writes the port using the buffer given to it.
-    Write(Port),
+    Write {
+        port: Port,
+        /// The lanes the bits go out on.
+        lanes: SpiLanes
+    },
 
     /// Reads a certain amount of bits from the port.
     Read {
         /// The bits to read.
         size: usize,
-        /// Skip the first bit.
-        first_clear: bool
-    }, 
+        /// The clocks to let pass before sampling, this covers the
+        /// turnaround bit and any dummy bytes.
+        skip: usize,
+        /// The lanes the bits come in on.
+        lanes: SpiLanes,
+        /// The request the bits belong to.
+        into: Arc<Completion>
+    },
+    /// Picks the chip select line the instructions that follow go to.
+    Select(usize),
+    /// Keeps CS low between the instructions that follow, until released.
+    Hold(bool),
+    /// Stops the clock for a while, CS stays where it is.
+    Delay(Duration),
     /// Wakes up a notifier.
-    Wake(Arc<AutoResetEvent>)
+    Wake(Arc<Completion>)
+}
+
+/// Signals that a queued request went out, along with any fault
+/// the slave reported while it was on the wire and the bits it read.
+pub(crate) struct Completion {
+    /// Set once the request is done.
+    event: AutoResetEvent,
+    /// The fault reported while the request was running.
+    fault: Mutex<Option<SpiError>>,
+    /// The bits read for this request, so concurrent requests can not
+    /// take each other's data.
+    data: Mutex<Port>,
+    /// The amount of instructions queued for this request.
+    queued: AtomicUsize
+}
+
+impl Completion {
+    fn new() -> Self {
+        Self {
+            event: AutoResetEvent::new(EventState::Unset),
+            fault: Mutex::new(None),
+            data: Mutex::new(Port::new()),
+            queued: AtomicUsize::new(0)
+        }
+    }
+    /// Waits until the request is done.
+    pub(crate) fn wait(&self) {
+        self.event.wait();
+    }
+    /// Waits until the request is done, returns `false` on timeout.
+    pub(crate) fn wait_for(&self, timeout: Duration) -> bool {
+        self.event.wait_for(timeout)
+    }
+    /// Checks if the request is done without blocking.
+    pub(crate) fn is_done(&self) -> bool {
+        self.event.wait0()
+    }
+    /// Takes the fault reported while the request was running.
+    pub(crate) fn take_fault(&self) -> Option<SpiError> {
+        self.fault.lock().unwrap().take()
+    }
 }
 
 
 impl SpiMaster<Disconnected> {
     /// Creates a new [SpiMaster] that is disconnected.
     pub fn new() -> Self {
+        Self::with_framing(SpiFraming::default())
+    }
+    /// Creates a new [SpiMaster] that frames commands with the given [SpiFraming].
+    ///
+    /// Panics if the framing does not pass [SpiFraming::validate].
+    pub fn with_framing(framing: SpiFraming) -> Self {
+        if let Err(error) = framing.validate() {
+            panic!("{}", error);
+        }
         Self {
             inner: Arc::new(SpiMasterInner {
                 instruction: Mutex::default(),
-                read_buf: Port::new().into(),
-                kill_switch: AtomicBool::new(false)
+                kill_switch: AtomicBool::new(false),
+                stopped: AtomicBool::new(false),
+                framing
             }),
-            _type: PhantomData
+            _type: PhantomData,
+            link: None,
+            cs: 0
         }
     }
     /// Connects a SPI master to a lsave.
     pub fn connect(self, slave: SpiSlave<Disconnected>, clock_speed: Duration) -> (SpiMaster<Connected>, SpiSlave<Connected>) {
-        let medium = Arc::new(SpiMedium {
-            clock: Clock::new(),
-            cs_select: LiveWire::new(),
-            miso: LiveWire::new(),
-            mosi: LiveWire::new(),
-            kill: LiveWire::new()
-        });
-
-        // Pull the CS select high to disable until the master is ready.
-        medium.cs_select.pull(true);
-
-        let connected = slave.accept_medium(&medium);
-
-        std::thread::spawn({
+        let (master, mut slaves) = self.connect_all(vec![slave], clock_speed);
+        (master, slaves.remove(0))
+    }
+    /// Connects a SPI master to several slaves sharing the clock and the data
+    /// lines, every slave gets its own chip select line in the order given.
+    ///
+    /// Requests go to the first slave, [SpiMaster::chip_select] hands out a
+    /// master that talks to another one.
+    pub fn connect_all(self, slaves: Vec<SpiSlave<Disconnected>>, clock_speed: Duration) -> (SpiMaster<Connected>, Vec<SpiSlave<Connected>>) {
+        let medium = Arc::new(SpiMedium::with_chip_selects(slaves.len()));
+
+        let connected = slaves.into_iter()
+            .enumerate()
+            .map(|(cs, slave)| slave.accept_medium_on(&medium, cs))
+            .collect();
+
+        let thread = std::thread::spawn({
             let inner = self.inner.clone();
+            let medium = medium.clone();
             move || handle_connection_master(inner, medium, clock_speed)
         });
-        (SpiMaster { inner: self.inner.clone(), _type: PhantomData }, connected)
+        let link = Link { inner: self.inner.clone(), thread: Mutex::new(Some(thread)), medium };
+        (SpiMaster { inner: self.inner, _type: PhantomData, link: Some(Arc::new(link)), cs: 0 }, connected)
+    }
+    /// Connects the master to a slave in a single threaded [SpiSimulation]
+    /// that runs in virtual time.
+    pub fn simulate(self, slave: SpiSlave<Disconnected>, clock_speed: Duration) -> SpiSimulation {
+        SpiSimulation::new(self, slave, clock_speed)
+    }
+    /// Turns the master into a stepper that is driven by the caller
+    /// instead of a thread.
+    pub(crate) fn into_stepper(self) -> MasterStepper {
+        MasterStepper::new(self.inner)
+    }
+    fn from_inner(inner: Arc<SpiMasterInner>) -> Self {
+        Self {
+            inner,
+            _type: PhantomData,
+            link: None,
+            cs: 0
+        }
     }
 }
 
-impl SpiMaster<Connected> {
-    /// Writes to register.
-    pub fn write_register(&self, reg: u8, bytes: Vec<u8>) {
-        let mut instruction_buffer = self.inner.instruction.lock().unwrap();
-
-        instruction_buffer.push_front(InstrVar::Write(Port::from_byte(reg)));
-        
-        for byte in bytes {
-            instruction_buffer.push_front(InstrVar::Write(Port::from_byte(byte)));
+/// Clones share the connection, every request carries its own result so
+/// several drivers can use the same bus from different threads.
+impl Clone for SpiMaster<Connected> {
+    fn clone(&self) -> Self {
+        Self {
+            inner: self.inner.clone(),
+            _type: PhantomData,
+            link: self.link.clone(),
+            cs: self.cs
         }
-        let waker = Arc::new(AutoResetEvent::new(EventState::Unset));
-        instruction_buffer.push_front(InstrVar::Wake(waker.clone()));
-
+    }
+}
 
-        drop(instruction_buffer);
+impl SpiMaster<Connected> {
+    /// Writes to register.
+    pub fn write_register(&self, reg: u32, bytes: Vec<u8>) -> Result<(), SpiError> {
+        let waker = self.queue(|inner| inner.queue_write(reg, bytes, self.cs))?;
 
         // Wait for the notification.
         waker.wait();
+        waker.take_fault().map_or(Ok(()), Err)
     }
     /// Reads a register.
-    pub fn read_register(&self, reg: u8, mut bytes: usize) -> Vec<u8> {
-        let mut instruction_buffer = self.inner.instruction.lock().unwrap();
+    pub fn read_register(&self, reg: u32, bytes: usize) -> Result<Vec<u8>, SpiError> {
+        let waker = self.queue(|inner| inner.queue_read(reg, bytes, self.cs))?;
 
-        instruction_buffer.push_front(InstrVar::Write(Port::from_byte(reg | 0x80)));
+        // Wait for the notification.
+        waker.wait();
+        if let Some(fault) = waker.take_fault() {
+            return Err(fault);
+        }
+        self.inner.take_read(&waker, bytes)
+    }
+    /// Writes to a register, failing if the write does not go out within `timeout`.
+    ///
+    /// A write that times out is taken off the queue, so it never goes out
+    /// later on. If it was already on the wire, the parts still queued are
+    /// dropped and the word in flight completes.
+    pub fn try_write_register(&self, reg: u32, bytes: Vec<u8>, timeout: Duration) -> Result<(), SpiError> {
+        let waker = self.queue(|inner| inner.queue_write(reg, bytes, self.cs))?;
+        if !waker.wait_for(timeout) && self.inner.cancel(&waker) {
+            return Err(SpiError::Timeout);
+        }
+        waker.take_fault().map_or(Ok(()), Err)
+    }
+    /// Reads a register, failing if the bytes do not come back within `timeout`.
+    ///
+    /// A read that times out is taken off the queue like a write.
+    pub fn try_read_register(&self, reg: u32, bytes: usize, timeout: Duration) -> Result<Vec<u8>, SpiError> {
+        let waker = self.queue(|inner| inner.queue_read(reg, bytes, self.cs))?;
+        if !waker.wait_for(timeout) && self.inner.cancel(&waker) {
+            return Err(SpiError::Timeout);
+        }
+        if let Some(fault) = waker.take_fault() {
+            return Err(fault);
+        }
+        self.inner.take_read(&waker, bytes)
+    }
+    /// Writes raw frames of [SpiFraming::word_bits] bits without a command header,
+    /// for devices like 9-bit display controllers or DACs.
+    pub fn write_words(&self, words: Vec<u32>) -> Result<(), SpiError> {
+        self.transfer(words, 0).map(|_| ())
+    }
+    /// Reads raw frames of [SpiFraming::word_bits] bits without sending a command header.
+    pub fn read_words(&self, count: usize) -> Result<Vec<u32>, SpiError> {
+        self.transfer(vec![], count)
+    }
+    /// Writes raw frames and then reads `read` frames back while keeping CS low,
+    /// this is how command based devices like flash chips are talked to.
+    pub fn transfer(&self, write: Vec<u32>, read: usize) -> Result<Vec<u32>, SpiError> {
+        let waker = self.queue(|inner| inner.queue_transfer(write, read, self.cs))?;
+        waker.wait();
+        if let Some(fault) = waker.take_fault() {
+            return Err(fault);
+        }
+        self.inner.take_words(&waker, read)
+    }
+    /// Runs a [SpiTransaction], returning the frames of every read operation.
+    pub fn execute(&self, transaction: &SpiTransaction) -> Result<Vec<Vec<u32>>, SpiError> {
+        let waker = self.queue(|inner| inner.queue_transaction(transaction, self.cs))?;
+        waker.wait();
+        if let Some(fault) = waker.take_fault() {
+            return Err(fault);
+        }
+        self.inner.take_transaction(&waker, transaction)
+    }
+    /// A handle on the same connection whose requests go to the slave on
+    /// the given chip select line.
+    pub fn chip_select(&self, index: usize) -> Self {
+        assert!(index < self.medium().chip_selects(), "There is no chip select line {}.", index);
+        Self { cs: index, ..self.clone() }
+    }
+    /// The wires shared with the slave, probes can be attached here.
+    pub fn medium(&self) -> &Arc<SpiMedium> {
+        &self.link().medium
+    }
+    fn link(&self) -> &Link {
+        self.link.as_ref().unwrap()
+    }
+    /// Queues a request on the connection this handle was made for.
+    ///
+    /// Fails with [SpiError::Disconnected] once that connection is torn down,
+    /// so a clone made before a disconnect can not reach a later connection
+    /// of the same master. The link stays locked while queueing, anything
+    /// that gets in before the teardown is failed by the drain in
+    /// [SpiMaster::disconnect_all].
+    fn queue(&self, queue: impl FnOnce(&SpiMasterInner) -> Arc<Completion>) -> Result<Arc<Completion>, SpiError> {
+        let thread = self.link().thread.lock().unwrap();
+        if thread.is_none()
+            || self.inner.kill_switch.load(Ordering::SeqCst)
+            || self.inner.stopped.load(Ordering::SeqCst)
+            || self.medium().detached.read()
+        {
+            return Err(SpiError::Disconnected);
+        }
+        let waker = queue(&self.inner);
+        // The thread may have exited while this was queued, after it failed
+        // what was waiting.
+        if self.inner.stopped.load(Ordering::SeqCst) {
+            self.inner.fail_pending();
+        }
+        Ok(waker)
+    }
+    /// Disconnects the master from the slave.
+    ///
+    /// This joins both the master and the slave thread, the register
+    /// contents of the slave are kept so the pair can be connected again.
+    /// Requests still waiting in the queue, from this master or any of its
+    /// clones, fail with [SpiError::Disconnected].
+    ///
+    /// Panics if the slave is connected to another master.
+    pub fn disconnect(self, slave: SpiSlave<Connected>) -> (SpiMaster<Disconnected>, SpiSlave<Disconnected>) {
+        let (master, mut slaves) = self.disconnect_all(vec![slave]);
+        (master, slaves.remove(0))
+    }
+    /// Disconnects the master from all of the slaves it was connected to,
+    /// see [SpiMaster::disconnect].
+    pub fn disconnect_all(self, slaves: Vec<SpiSlave<Connected>>) -> (SpiMaster<Disconnected>, Vec<SpiSlave<Disconnected>>) {
+        assert!(slaves.iter().all(|slave| slave.is_on(self.medium())), "The slave is not connected to this master.");
+        self.link().stop();
+        let slaves = slaves.into_iter().map(|slave| slave.join()).collect();
+
+        // Clear out whatever was left over so the next connection starts clean.
+        self.inner.kill_switch.store(false, Ordering::SeqCst);
+        self.inner.stopped.store(false, Ordering::SeqCst);
+        self.inner.fail_pending();
+
+        (SpiMaster::from_inner(self.inner), slaves)
+    }
+}
+
+
+impl SpiMasterInner {
+    /// Empties the queue, failing every request in it with [SpiError::Disconnected].
+    fn fail_pending(&self) {
+        for instruction in self.instruction.lock().unwrap().drain(..) {
+            if let InstrVar::Wake(wake) = instruction {
+                *wake.fault.lock().unwrap() = Some(SpiError::Disconnected);
+                wake.event.set();
+            }
+        }
+    }
+    /// Queues up a register write, the returned event is set once it went out.
+    pub(crate) fn queue_write(&self, reg: u32, bytes: Vec<u8>, cs: usize) -> Arc<Completion> {
+        let mut instruction_buffer = self.instruction.lock().unwrap();
+        let start = instruction_buffer.len();
+        instruction_buffer.push_front(InstrVar::Select(cs));
+
+        for word in self.framing.encode(reg, false, bytes.len() > self.framing.word_bytes()) {
+            instruction_buffer.push_front(self.frame(word, SpiLanes::Single));
+        }
+        
+        for word in self.framing.pack(&bytes) {
+            instruction_buffer.push_front(self.frame(word, self.framing.data_lanes));
+        }
+        let waker = Arc::new(Completion::new());
+        instruction_buffer.push_front(InstrVar::Wake(waker.clone()));
+        waker.queued.store(instruction_buffer.len() - start, Ordering::SeqCst);
+        waker
+    }
+    /// Queues up a register read, the returned event is set once the
+    /// bytes are in the read buffer.
+    pub(crate) fn queue_read(&self, reg: u32, bytes: usize, cs: usize) -> Arc<Completion> {
+        let waker = Arc::new(Completion::new());
+        let mut instruction_buffer = self.instruction.lock().unwrap();
+        let start = instruction_buffer.len();
+        instruction_buffer.push_front(InstrVar::Select(cs));
+
+        for word in self.framing.encode(reg, true, bytes > self.framing.word_bytes()) {
+            instruction_buffer.push_front(self.frame(word, SpiLanes::Single));
+        }
+        let lanes = self.framing.data_lanes;
         instruction_buffer.push_front(InstrVar::Read {
-            size: bytes * 8,
-            first_clear: true
+            size: bytes.div_ceil(self.framing.word_bytes()) * self.framing.word_bits,
+            skip: 1 + self.framing.dummy_bytes * 8 / lanes.width(),
+            lanes,
+            into: waker.clone()
         });
-        let waker = Arc::new(AutoResetEvent::new(EventState::Unset));
         instruction_buffer.push_front(InstrVar::Wake(waker.clone()));
+        waker.queued.store(instruction_buffer.len() - start, Ordering::SeqCst);
+        waker
+    }
+    /// Queues up raw frames followed by a read of `read` frames, all
+    /// without a command header and under a single CS assertion.
+    pub(crate) fn queue_transfer(&self, write: Vec<u32>, read: usize, cs: usize) -> Arc<Completion> {
+        let waker = Arc::new(Completion::new());
+        let mut instruction_buffer = self.instruction.lock().unwrap();
+        let start = instruction_buffer.len();
+        instruction_buffer.push_front(InstrVar::Select(cs));
+
+        for word in write {
+            instruction_buffer.push_front(self.frame(word, SpiLanes::Single));
+        }
+        if read > 0 {
+            instruction_buffer.push_front(InstrVar::Read {
+                size: read * self.framing.word_bits,
+                skip: 1,
+                lanes: SpiLanes::Single,
+                into: waker.clone()
+            });
+        }
+        instruction_buffer.push_front(InstrVar::Wake(waker.clone()));
+        waker.queued.store(instruction_buffer.len() - start, Ordering::SeqCst);
+        waker
+    }
+    /// Queues up the operations of a transaction, CS is held low from the
+    /// first to the last one and nothing else can get in between.
+    pub(crate) fn queue_transaction(&self, transaction: &SpiTransaction, cs: usize) -> Arc<Completion> {
+        let waker = Arc::new(Completion::new());
+        let mut instruction_buffer = self.instruction.lock().unwrap();
+        let start = instruction_buffer.len();
+        instruction_buffer.push_front(InstrVar::Select(cs));
+
+        instruction_buffer.push_front(InstrVar::Hold(true));
+        for operation in transaction.operations() {
+            match operation {
+                SpiOperation::Write(words) => {
+                    for word in words {
+                        instruction_buffer.push_front(self.frame(*word, SpiLanes::Single));
+                    }
+                }
+                SpiOperation::Read(count) if *count > 0 => {
+                    instruction_buffer.push_front(InstrVar::Read {
+                        size: count * self.framing.word_bits,
+                        skip: 1,
+                        lanes: SpiLanes::Single,
+                        into: waker.clone()
+                    });
+                }
+                SpiOperation::Read(_) => {}
+                SpiOperation::Delay(delay) => instruction_buffer.push_front(InstrVar::Delay(*delay))
+            }
+        }
+        instruction_buffer.push_front(InstrVar::Hold(false));
+        instruction_buffer.push_front(InstrVar::Wake(waker.clone()));
+        waker.queued.store(instruction_buffer.len() - start, Ordering::SeqCst);
+        waker
+    }
+    /// Takes a request that timed out off the queue so it does not run later.
+    ///
+    /// Returns `false` if the request was already done.
+    pub(crate) fn cancel(&self, request: &Arc<Completion>) -> bool {
+        let mut instruction_buffer = self.instruction.lock().unwrap();
+        // The wake up is the newest instruction of a request, everything
+        // still left of it follows towards the back of the queue.
+        let Some(wake) = instruction_buffer.iter()
+            .position(|i| matches!(i, InstrVar::Wake(w) if Arc::ptr_eq(w, request)))
+        else {
+            return false;
+        };
+        let end = (wake + request.queued.load(Ordering::SeqCst)).min(instruction_buffer.len());
+        instruction_buffer.drain(wake..end);
+        // A transaction may have been holding CS low.
+        instruction_buffer.insert(wake, InstrVar::Hold(false));
+        true
+    }
+    /// Splits the frames read by a transaction up by read operation.
+    pub(crate) fn take_transaction(&self, request: &Completion, transaction: &SpiTransaction) -> Result<Vec<Vec<u32>>, SpiError> {
+        transaction.reads().map(|count| self.take_words(request, count)).collect()
+    }
+    /// A single frame going out on the given lanes.
+    fn frame(&self, word: u32, lanes: SpiLanes) -> InstrVar {
+        let mut port = Port::new();
+        port.write_word(word, self.framing.word_bits);
+        InstrVar::Write { port, lanes }
+    }
+    /// Takes the bytes read by a request.
+    pub(crate) fn take_read(&self, request: &Completion, bytes: usize) -> Result<Vec<u8>, SpiError> {
+        let words = self.take_words(request, bytes.div_ceil(self.framing.word_bytes())).map_err(|err| match err {
+            SpiError::ShortRead { received, .. } => SpiError::ShortRead {
+                expected: bytes,
+                received: received * self.framing.word_bytes()
+            },
+            err => err
+        })?;
+        Ok(self.framing.unpack(&words, bytes))
+    }
+    /// Takes the frames read by a request.
+    pub(crate) fn take_words(&self, request: &Completion, count: usize) -> Result<Vec<u32>, SpiError> {
+        let mut read_buffer = request.data.lock().unwrap();
+        let mut buf = vec![];
+        while buf.len() < count {
+            match read_buffer.read_word(self.framing.word_bits) {
+                Some(word) => buf.push(word),
+                None => {
+                    read_buffer.clear();
+                    return Err(SpiError::ShortRead { expected: count, received: buf.len() });
+                }
+            }
+        }
+        Ok(buf)
+    }
+}
 
+/// Runs the master side of the protocol one clock phase at a time.
+pub(crate) struct MasterStepper {
+    inner: Arc<SpiMasterInner>,
+    state: StepperState
+}
 
-        drop(instruction_buffer);
-
-        // Wait for the notification.
-        waker.wait();
-
+struct StepperState {
+    /// The instruction currently being executed.
+    ctx: Option<InstrVar>,
+    /// If a transaction is keeping CS low.
+    held: bool,
+    /// The chip select line of the current request.
+    cs: usize,
+    /// How long the clock should stop before the next tick.
+    delay: Duration
+}
 
-        // Read out the buffers.
-        let mut read_buffer = self.inner.read_buf.lock().unwrap();
-        let mut buf = vec![];
-        while bytes > 0 {
-            buf.push(read_buffer.read_byte().unwrap());
-            bytes -= 1;
+impl MasterStepper {
+    pub(crate) fn new(inner: Arc<SpiMasterInner>) -> Self {
+        Self {
+            inner,
+            state: StepperState {
+                ctx: None,
+                held: false,
+                cs: 0,
+                delay: Duration::ZERO
+            }
         }
-        buf
     }
-    /// Disconnects the master from the slave.
-    pub fn disconnect(self) -> SpiMaster<Disconnected> {
-        self.inner.kill_switch.store(true, Ordering::SeqCst);
-        SpiMaster {
-            inner: self.inner.clone(),
-            _type: PhantomData
+    /// Turns the stepper back into a disconnected master.
+    pub(crate) fn into_master(self) -> SpiMaster<Disconnected> {
+        SpiMaster::from_inner(self.inner)
+    }
+    /// The state shared with the master handle.
+    pub(crate) fn inner(&self) -> &SpiMasterInner {
+        &self.inner
+    }
+    /// Called after every clock tick with the new clock level.
+    pub(crate) fn on_clock(&mut self, medium: &SpiMedium, clock: bool) {
+        if !clock {
+            // Performs logic while the line is pulled down low.
+            handle_low_level(&self.inner, medium, &mut self.state);
         }
     }
+    /// Takes the time the clock has to stop for before the next tick.
+    pub(crate) fn take_delay(&mut self) -> Duration {
+        std::mem::take(&mut self.state.delay)
+    }
 }
 
+/// Stops the slave and fails every waiting request once the master thread
+/// exits, even when it panics.
+struct StopGuard {
+    master: Arc<SpiMasterInner>,
+    medium: Arc<SpiMedium>
+}
+
+impl Drop for StopGuard {
+    fn drop(&mut self) {
+        self.medium.kill.pull(true); // Kill the slave.
+        // Wake it up so it sees the kill line, without the probes in case
+        // one of them is what took the thread down.
+        self.medium.clock.idle_with(|_, _| {});
+        self.master.stopped.store(true, Ordering::SeqCst);
+        self.master.fail_pending();
+    }
+}
 
 /// Handles the connection from the master side.
 fn handle_connection_master(
@@ -155,25 +611,21 @@ fn handle_connection_master(
     medium: Arc<SpiMedium>,
     duration: Duration,
 ) {
-    let mut ctx = None;
+    let _guard = StopGuard { master: master.clone(), medium: medium.clone() };
+    let mut stepper = MasterStepper::new(master.clone());
 
- 
     loop {
-        // tick the cloc.
-        medium.clock.tick();
+        // tick the cloc, it stays low while chip select is high.
+        medium.gated_tick();
 
         if master.kill_switch.load(std::sync::atomic::Ordering::SeqCst) {
-            medium.kill.pull(true); // Kill the slave.
             break;
         }
 
-        if !medium.clock.get_line_value() {
-            // Performs logic while the line is pulled down low.
-            handle_low_level(&master, &medium, &mut ctx);
-        }
+        stepper.on_clock(&medium, medium.clock.get_line_value());
 
         // Sleep
-        sleep(duration);
+        sleep(duration + stepper.take_delay());
     }
 }
 
@@ -181,77 +633,97 @@ fn handle_connection_master(
 fn handle_low_level(
     master: &SpiMasterInner,
     medium: &SpiMedium,
-    ctx: &mut Option<InstrVar>
+    stepper: &mut StepperState
 ) {
-
-    if let Some(InstrVar::Write(port)) = ctx {
-        if port.bits_read() == 0 {
+    let ctx = &mut stepper.ctx;
+    loop {
+        if let Some(InstrVar::Write { port, .. }) = ctx
+            && port.bits_read() == 0
+        {
             *ctx = None;
         }
-    }
-    
-    // Load the instruction
-    if ctx.is_none() {
-        *ctx = master.instruction.lock().unwrap().pop_back();
-    } 
-
-
-    if ctx.is_some() {
-        match ctx.as_mut().unwrap() {
-            InstrVar::Read {
-                first_clear,
-                size: count
-            } => {
-    
-                if *first_clear {
-                    *first_clear = false;
-                } else {
-                    medium.cs_select.pull(false); // pull line down.
-    
+
+        // Load the instruction
+        if ctx.is_none() {
+            *ctx = master.instruction.lock().unwrap().pop_back();
+        }
+
+        match ctx {
+            None => break,
+            Some(InstrVar::Read {
+                skip,
+                size: count,
+                lanes,
+                into
+            }) => {
+                // Turn the lines around so the slave can drive them, this
+                // matters after a dual or quad write.
+                for wire in medium.data_lines(*lanes, false) {
+                    wire.release(Driver::PRIMARY);
+                }
+
+                medium.chip_select(stepper.cs).pull(false); // pull line down.
+                if *skip != 0 {
+                    *skip -= 1;
+                    break;
+                }
+                if *count != 0 {
+                    let mut read_buf = into.data.lock().unwrap();
+                    for wire in medium.data_lines(*lanes, false) {
+                        read_buf.write(wire.read());
+                    }
+                    *count = count.saturating_sub(lanes.width());
                     if *count != 0 {
-                        master.read_buf.lock().unwrap().write(medium.miso.read());
-                        *count -= 1;
-                        if *count == 0 {
-                            *ctx = None;
-                        }
-                    } else {
-                        *ctx = None;
+                        break;
                     }
-                    
                 }
-    
-    
-                
-                
+                *ctx = None;
+                if !stepper.held {
+                    break;
+                }
+                // Inside a transaction the next operation starts right away,
+                // an idle clock would be taken as data by the slave.
             },
-            InstrVar::Write(p) => {
-                if p.bits_read() == 0 {
-                    // We are done with the instruction.
-                    *ctx = None;
-                } else {
-                    medium.cs_select.pull(false); // pull line down
-                    medium.mosi.pull(p.read().unwrap());
-                }  
+            Some(InstrVar::Write { port: p, lanes }) => {
+                medium.chip_select(stepper.cs).pull(false); // pull line down
+                for wire in medium.data_lines(*lanes, true) {
+                    wire.pull(p.read().unwrap_or(false));
+                }
+                break;
             },
-            InstrVar::Wake(wake) => {
-                wake.set();
+            Some(InstrVar::Select(cs)) => {
+                if *cs != stepper.cs {
+                    medium.deselect(stepper.cs);
+                    stepper.cs = *cs;
+                }
+                *ctx = None;
+            }
+            Some(InstrVar::Hold(held)) => {
+                stepper.held = *held;
+                *ctx = None;
+            }
+            Some(InstrVar::Delay(delay)) => {
+                stepper.delay += *delay;
                 *ctx = None;
             }
+            Some(InstrVar::Wake(wake)) => {
+                let mut fault = medium.take_fault();
+                if medium.detached.read() {
+                    fault = Some(SpiError::Disconnected);
+                }
+                *wake.fault.lock().unwrap() = fault;
+                wake.event.set();
+                *ctx = None;
+                break;
+            }
         }
     }
-    
-
-
 
-    if ctx.is_none() {
-        medium.cs_select.pull(true); // Pull the CS line HIGH.
+    if ctx.is_none() && !stepper.held {
+        medium.deselect(stepper.cs); // Pull the CS line HIGH.
     }
-
 }
 
-
-
-
 impl Default for SpiMaster<Disconnected> {
     fn default() -> Self {
         Self::new()
@@ -0,0 +1,173 @@
+use std::{fmt::{self, Display}, sync::{Arc, Mutex}};
+
+use crate::core::mock::Expectations;
+
+use super::{
+    device::{SpiContext, SpiDevice},
+    framing::{SpiCommand, SpiFraming},
+    master::Disconnected,
+    slave::SpiSlave,
+};
+
+/// A register access a [SpiMock] expects, in frames of the framing used.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum SpiExpectation {
+    Write { register: u32, words: Vec<u32> },
+    /// A read, answered with `response` after the dummy bytes of the framing.
+    Read { register: u32, response: Vec<u32> }
+}
+
+impl SpiExpectation {
+    pub fn write(register: u32, words: Vec<u32>) -> Self {
+        Self::Write { register, words }
+    }
+    pub fn read(register: u32, response: Vec<u32>) -> Self {
+        Self::Read { register, response }
+    }
+}
+
+impl Display for SpiExpectation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SpiExpectation::Write { register, words } => write!(f, "write [{:#04X}] {:02X?}", register, words),
+            SpiExpectation::Read { register, response } => write!(f, "read [{:#04X}] -> {:02X?}", register, response)
+        }
+    }
+}
+
+/// A [SpiDevice] that decodes register accesses with a [SpiFraming], checks
+/// them against the accesses the driver is expected to make, in order, and
+/// answers the reads with the words given.
+///
+/// The device sits behind a regular [SpiSlave], so the wires behave exactly
+/// like they do for a modelled device. When the mock is dropped it panics
+/// with a diff if anything was unexpected or missing, call [SpiMock::done]
+/// to check earlier.
+pub struct SpiMock {
+    framing: SpiFraming,
+    expectations: Arc<Mutex<Expectations<SpiExpectation>>>
+}
+
+impl SpiMock {
+    pub fn new(framing: SpiFraming, expectations: impl IntoIterator<Item = SpiExpectation>) -> Self {
+        Self {
+            framing,
+            expectations: Arc::new(Mutex::new(Expectations::new(expectations)))
+        }
+    }
+    /// The slave to connect the master to.
+    pub fn slave(&self) -> SpiSlave<Disconnected> {
+        SpiSlave::from_device(MockDevice {
+            framing: self.framing,
+            expectations: self.expectations.clone(),
+            header: vec![],
+            command: None,
+            data: vec![]
+        })
+    }
+    /// A diff of the expected and the actual accesses, if they differ.
+    pub fn report(&self) -> Option<String> {
+        let mut expectations = self.expectations.lock().unwrap();
+        expectations.checked = true;
+        expectations.report()
+    }
+    /// Panics with a diff if the accesses did not go as expected.
+    pub fn done(&mut self) {
+        if let Some(diff) = self.report() {
+            panic!("SPI mock saw something else than expected:\n{}", diff);
+        }
+    }
+}
+
+impl Drop for SpiMock {
+    fn drop(&mut self) {
+        let checked = self.expectations.lock().unwrap().checked;
+        if !checked && !std::thread::panicking() {
+            self.done();
+        }
+    }
+}
+
+/// The device behind the slave of a [SpiMock].
+struct MockDevice {
+    framing: SpiFraming,
+    expectations: Arc<Mutex<Expectations<SpiExpectation>>>,
+    header: Vec<u32>,
+    command: Option<SpiCommand>,
+    /// The frames written after the header.
+    data: Vec<u32>
+}
+
+impl SpiDevice for MockDevice {
+    fn word_bits(&self) -> usize {
+        self.framing.word_bits
+    }
+    fn on_word(&mut self, word: u32, ctx: &mut SpiContext) {
+        if self.command.is_some() {
+            self.data.push(word);
+            return;
+        }
+        self.header.push(word);
+        if self.header.len() < self.framing.header_words {
+            return;
+        }
+        let command = self.framing.decode(&self.header);
+        self.command = Some(command);
+        if !command.read {
+            return;
+        }
+        let matches = |e: &SpiExpectation, a: &SpiExpectation| matches!(
+            (e, a),
+            (SpiExpectation::Read { register: e, .. }, SpiExpectation::Read { register: a, .. }) if e == a
+        );
+        let actual = SpiExpectation::Read { register: command.register, response: vec![] };
+        if let Some(SpiExpectation::Read { response, .. }) = self.expectations.lock().unwrap().check(actual, matches) {
+            for _ in 0..self.framing.dummy_bytes {
+                ctx.send_byte(0x00);
+            }
+            for word in response {
+                ctx.send_word(word);
+            }
+        }
+    }
+    fn on_deselect(&mut self) {
+        if let Some(command) = self.command.take()
+            && !command.read
+        {
+            let actual = SpiExpectation::Write { register: command.register, words: std::mem::take(&mut self.data) };
+            self.expectations.lock().unwrap().check(actual, |e, a| e == a);
+        }
+        self.header.clear();
+        self.data.clear();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use crate::spi::{framing::SpiFraming, master::SpiMaster};
+
+    use super::{SpiExpectation, SpiMock};
+
+    #[test]
+    pub fn test_mock() {
+        let mock = SpiMock::new(SpiFraming::bmi270(), [
+            SpiExpectation::read(0x00, vec![ 0x24 ]),
+            SpiExpectation::write(0x7D, vec![ 0x0E ]),
+            SpiExpectation::read(0x7E, vec![ 0x01 ])
+        ]);
+        let mut sim = SpiMaster::with_framing(SpiFraming::bmi270()).simulate(mock.slave(), Duration::from_micros(1));
+
+        assert_eq!(sim.read_register(0x00, 1), vec![ 0x24 ]);
+        sim.write_register(0x7D, vec![ 0x02 ]);
+        sim.run(4);
+
+        assert_eq!(mock.report().unwrap(), [
+            "  read [0x00] -> [24]",
+            "- write [0x7D] [0E]",
+            "+ write [0x7D] [02]",
+            "- read [0x7E] -> [01]"
+        ].join("\n"));
+    }
+}
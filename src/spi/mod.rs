@@ -1,4 +1,19 @@
 pub mod master;
 pub mod clock;
 pub mod wire;
-pub mod slave;
\ No newline at end of file
+pub mod slave;
+pub mod framing;
+pub mod sim;
+pub mod error;
+pub mod fault;
+pub mod device;
+pub mod flash;
+pub mod sdcard;
+pub mod probe;
+pub mod compliance;
+pub mod transaction;
+pub mod record;
+pub mod waveform;
+pub mod decode;
+pub mod golden;
+pub mod mock;
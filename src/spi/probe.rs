@@ -0,0 +1,50 @@
+use std::sync::{Arc, Mutex};
+
+use super::wire::{Driver, Level};
+
+/// The state of a data line in a [SpiSample].
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct LineSample {
+    pub level: Level,
+    /// Who is driving the line, if anyone.
+    pub driver: Option<Driver>
+}
+
+/// The wires of a [SpiMedium](super::wire::SpiMedium) once both sides are
+/// done with a clock edge and before the next one.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct SpiSample {
+    /// The edge this sample follows, the first sample comes before any edge.
+    pub edge: u64,
+    /// The clock level.
+    pub clock: bool,
+    pub cs: Level,
+    pub mosi: LineSample,
+    pub miso: LineSample,
+    pub io2: LineSample,
+    pub io3: LineSample
+}
+
+impl SpiSample {
+    /// Checks if chip select is asserted.
+    pub fn selected(&self) -> bool {
+        self.cs == Level::Low
+    }
+    /// The data lines along with their names, from IO0 to IO3.
+    pub fn lines(&self) -> [(&'static str, LineSample); 4] {
+        [("MOSI", self.mosi), ("MISO", self.miso), ("IO2", self.io2), ("IO3", self.io3)]
+    }
+}
+
+/// Watches a [SpiMedium](super::wire::SpiMedium) one clock phase at a time.
+pub trait SpiProbe: Send {
+    fn on_sample(&mut self, sample: &SpiSample);
+}
+
+/// Lets a test attach a probe to the medium and still read it out once the
+/// link is torn down.
+impl<P: SpiProbe> SpiProbe for Arc<Mutex<P>> {
+    fn on_sample(&mut self, sample: &SpiSample) {
+        self.lock().unwrap().on_sample(sample);
+    }
+}
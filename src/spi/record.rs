@@ -0,0 +1,170 @@
+use std::fmt::{self, Display};
+
+use super::{framing::{SpiCommand, SpiFraming}, probe::{SpiProbe, SpiSample}, wire::Level};
+
+/// Everything clocked while chip select was asserted once.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct SpiRecord {
+    /// The clock cycle chip select was asserted at.
+    pub start: u64,
+    /// The clock cycle chip select was released at.
+    pub end: u64,
+    /// The frames on MOSI, sampled on every rising edge.
+    pub mosi: Vec<u32>,
+    /// The frames on MISO, sampled on the same edges.
+    pub miso: Vec<u32>,
+    /// The command header, decoded with the framing of the recorder.
+    pub command: Option<SpiCommand>,
+    /// The frames after the header, from MISO for reads and from MOSI for writes.
+    pub data: Vec<u32>
+}
+
+/// Records every transaction on a single lane SPI link, attach it with
+/// [SpiMedium::add_probe](super::wire::SpiMedium::add_probe) behind an `Arc<Mutex<_>>`.
+pub struct SpiRecorder {
+    framing: SpiFraming,
+    records: Vec<SpiRecord>,
+    /// The transaction still in progress.
+    current: Option<SpiRecord>,
+    /// The bits of the frames still being clocked.
+    bits: usize,
+    previous: Option<SpiSample>
+}
+
+impl SpiRecorder {
+    /// Creates a recorder that splits frames and decodes headers with the given framing.
+    pub fn new(framing: SpiFraming) -> Self {
+        Self {
+            framing,
+            records: vec![],
+            current: None,
+            bits: 0,
+            previous: None
+        }
+    }
+    /// Every completed transaction.
+    pub fn records(&self) -> &[SpiRecord] {
+        &self.records
+    }
+    /// Takes the completed transactions.
+    pub fn take(&mut self) -> Vec<SpiRecord> {
+        std::mem::take(&mut self.records)
+    }
+    /// The data of every write to a register.
+    pub fn writes_to(&self, register: u32) -> Vec<Vec<u32>> {
+        self.matching(register, false)
+    }
+    /// The data of every read from a register.
+    pub fn reads_from(&self, register: u32) -> Vec<Vec<u32>> {
+        self.matching(register, true)
+    }
+    fn matching(&self, register: u32, read: bool) -> Vec<Vec<u32>> {
+        self.records.iter()
+            .filter(|r| r.command.is_some_and(|c| c.register == register && c.read == read))
+            .map(|r| r.data.clone())
+            .collect()
+    }
+    fn finish(&mut self, mut record: SpiRecord, end: u64) {
+        record.end = end;
+        // Leave out whatever did not make up a full frame.
+        if self.bits != 0 {
+            record.mosi.pop();
+            record.miso.pop();
+            self.bits = 0;
+        }
+        record.decode(&self.framing);
+        self.records.push(record);
+    }
+}
+
+impl SpiRecord {
+    /// Fills in the command and the data from the frames.
+    pub(crate) fn decode(&mut self, framing: &SpiFraming) {
+        let header = framing.header_words;
+        if self.mosi.len() < header {
+            return;
+        }
+        let command = framing.decode(&self.mosi[..header]);
+        self.data = if command.read {
+            let dummy = framing.dummy_bytes.div_ceil(framing.word_bytes());
+            self.miso.iter().skip(header + dummy).copied().collect()
+        } else {
+            self.mosi[header..].to_vec()
+        };
+        self.command = Some(command);
+    }
+}
+
+/// Renders the frames the way the README writes I2C, e.g. `[ 0x80 ]( 0xFF )[ 0x00 ]( 0x24 )`.
+/// Every frame on MOSI is in `[]`, followed by the frame on MISO in `()`.
+impl Display for SpiRecord {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (mosi, miso) in self.mosi.iter().zip(&self.miso) {
+            write!(f, "[ {:#04X} ]( {:#04X} )", mosi, miso)?;
+        }
+        Ok(())
+    }
+}
+
+impl Default for SpiRecorder {
+    fn default() -> Self {
+        Self::new(SpiFraming::default())
+    }
+}
+
+impl SpiProbe for SpiRecorder {
+    fn on_sample(&mut self, sample: &SpiSample) {
+        let cycle = sample.edge / 2;
+        let rising = sample.clock && self.previous.is_some_and(|p| !p.clock);
+        self.previous = Some(*sample);
+
+        if !sample.selected() {
+            if let Some(record) = self.current.take() {
+                self.finish(record, cycle);
+            }
+            return;
+        }
+        let record = self.current.get_or_insert_with(|| SpiRecord {
+            start: cycle,
+            end: cycle,
+            mosi: vec![],
+            miso: vec![],
+            command: None,
+            data: vec![]
+        });
+        if !rising {
+            return;
+        }
+        if self.bits == 0 {
+            record.mosi.push(0);
+            record.miso.push(0);
+        }
+        // A floating line reads as zero.
+        let bit = |level: Level| (level == Level::High) as u32;
+        *record.mosi.last_mut().unwrap() = record.mosi.last().unwrap() << 1 | bit(sample.mosi.level);
+        *record.miso.last_mut().unwrap() = record.miso.last().unwrap() << 1 | bit(sample.miso.level);
+        self.bits = (self.bits + 1) % self.framing.word_bits;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::testing;
+
+    #[test]
+    pub fn test_recorder() {
+        let (mut sim, recorder) = testing::spi_recorded(testing::spi_bmi270());
+
+        sim.write_register(0x7D, vec![ 0x02 ]);
+        sim.write_register(0x7D, vec![ 0x0E ]);
+        assert_eq!(sim.read_register(0x7D, 1), vec![ 0x0E ]);
+        sim.run(4);
+
+        let recorder = recorder.lock().unwrap();
+        assert_eq!(recorder.writes_to(0x7D).iter().filter(|data| **data == [ 0x02 ]).count(), 1);
+        assert_eq!(recorder.reads_from(0x7D), vec![ vec![ 0x0E ] ]);
+        // The read is the header, the dummy byte and the data.
+        assert_eq!(recorder.records()[2].mosi.len(), 3);
+        assert!(recorder.records()[1].start > recorder.records()[0].end);
+    }
+}
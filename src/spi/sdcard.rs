@@ -0,0 +1,436 @@
+use std::{fs::File, io::{self, Read, Seek, SeekFrom, Write}, path::Path};
+
+use crate::core::{DeviceTracer, TraceEvent, Verbosity};
+
+use super::device::{SpiContext, SpiDevice};
+
+/// The size of a data block, SDHC cards always use 512 bytes.
+pub const BLOCK_SIZE: usize = 512;
+
+/// The token that starts a single block of data.
+pub const START_BLOCK: u8 = 0xFE;
+
+/// The R1 response bits.
+pub mod r1 {
+    pub const IDLE: u8 = 0x01;
+    pub const ILLEGAL_COMMAND: u8 = 0x04;
+    pub const CRC_ERROR: u8 = 0x08;
+    pub const PARAMETER_ERROR: u8 = 0x40;
+}
+
+/// The data response tokens sent after a block was written.
+pub mod data_response {
+    pub const ACCEPTED: u8 = 0x05;
+    pub const CRC_ERROR: u8 = 0x0B;
+    pub const WRITE_ERROR: u8 = 0x0D;
+}
+
+/// The commands understood by [SdCard], application commands have to be
+/// preceded by [APP_CMD](command::APP_CMD).
+pub mod command {
+    pub const GO_IDLE_STATE: u8 = 0;
+    pub const SEND_IF_COND: u8 = 8;
+    pub const SET_BLOCKLEN: u8 = 16;
+    pub const READ_SINGLE_BLOCK: u8 = 17;
+    pub const WRITE_BLOCK: u8 = 24;
+    pub const APP_CMD: u8 = 55;
+    pub const READ_OCR: u8 = 58;
+    pub const CRC_ON_OFF: u8 = 59;
+    /// Application command.
+    pub const SD_SEND_OP_COND: u8 = 41;
+}
+
+/// The OCR bit set once the card finished powering up.
+pub const OCR_POWER_UP: u32 = 1 << 31;
+/// The OCR bit set for high capacity cards, which are addressed in blocks.
+pub const OCR_CCS: u32 = 1 << 30;
+
+/// Computes the 7-bit CRC ending a command frame, already shifted in
+/// place with the end bit set.
+pub fn crc7(bytes: &[u8]) -> u8 {
+    let mut crc = 0u8;
+    for byte in bytes {
+        for bit in (0..8).rev() {
+            let feedback = ((byte >> bit) & 1) ^ (crc >> 6);
+            crc = (crc << 1) & 0x7F;
+            if feedback != 0 {
+                crc ^= 0x09;
+            }
+        }
+    }
+    (crc << 1) | 1
+}
+
+/// Computes the CRC-16/XMODEM protecting a block of data.
+pub fn crc16(bytes: &[u8]) -> u16 {
+    let mut crc = 0u16;
+    for byte in bytes {
+        crc ^= (*byte as u16) << 8;
+        for _ in 0..8 {
+            crc = if crc & 0x8000 != 0 { (crc << 1) ^ 0x1021 } else { crc << 1 };
+        }
+    }
+    crc
+}
+
+/// Builds the six byte frame of a command.
+pub fn command_frame(index: u8, argument: u32) -> [u8; 6] {
+    let mut frame = [0x40 | index, 0, 0, 0, 0, 0];
+    frame[1..5].copy_from_slice(&argument.to_be_bytes());
+    frame[5] = crc7(&frame[..5]);
+    frame
+}
+
+/// A high capacity SD card spoken to in SPI mode.
+///
+/// Every command is answered one byte after its frame with an R1 response,
+/// followed by the rest of the response and data blocks. Written blocks keep
+/// the card busy for a while after the data response, the card holds MISO low
+/// until it is done and then sends a single 0xFF.
+pub struct SdCard {
+    image: Vec<u8>,
+    /// The file written through to, if the card was opened from one.
+    file: Option<File>,
+    /// If the card finished initializing and left the idle state.
+    ready: bool,
+    /// The ACMD41 polls needed to initialize the card.
+    init_delay: usize,
+    /// The ACMD41 polls left before the card is ready.
+    init_polls: usize,
+    /// If the previous command was APP_CMD.
+    app_command: bool,
+    /// If CRCs are checked on every command and data block.
+    crc_enabled: bool,
+    /// How long writing a block keeps the card busy, in clock cycles.
+    write_time: u64,
+    state: CardState,
+    trace: DeviceTracer
+}
+
+enum CardState {
+    /// Collecting the bytes of a command frame.
+    Command(Vec<u8>),
+    /// Waiting for the start token of a block to write.
+    AwaitToken {
+        block: u32
+    },
+    /// Collecting the data and CRC of a block to write.
+    Receiving {
+        block: u32,
+        data: Vec<u8>
+    },
+    /// Writing a block, until the given cycle.
+    Busy {
+        until: u64
+    }
+}
+
+impl SdCard {
+    /// Creates a blank card holding the given amount of blocks.
+    pub fn new(blocks: usize) -> Self {
+        Self::with_image(vec![0x00; blocks * BLOCK_SIZE])
+    }
+    /// Creates a card holding the given image, which must be a whole amount of blocks.
+    pub fn with_image(image: Vec<u8>) -> Self {
+        assert!(image.len().is_multiple_of(BLOCK_SIZE), "The image must be a whole amount of blocks.");
+        Self {
+            image,
+            file: None,
+            ready: false,
+            init_delay: 1,
+            init_polls: 1,
+            app_command: false,
+            crc_enabled: false,
+            write_time: 64,
+            state: CardState::Command(vec![]),
+            trace: DeviceTracer::default()
+        }
+    }
+    /// Opens a card backed by an image file, written blocks go straight to the file.
+    pub fn open(path: impl AsRef<Path>) -> io::Result<Self> {
+        let mut file = File::options().read(true).write(true).open(path)?;
+        let mut image = vec![];
+        file.read_to_end(&mut image)?;
+        if !image.len().is_multiple_of(BLOCK_SIZE) {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, "The image must be a whole amount of blocks."));
+        }
+        let mut card = Self::with_image(image);
+        card.file = Some(file);
+        Ok(card)
+    }
+    /// Sets how many times ACMD41 has to be sent before the card is ready.
+    pub fn with_init_polls(mut self, polls: usize) -> Self {
+        self.init_delay = polls;
+        self.init_polls = polls;
+        self
+    }
+    /// Sets how long writing a block keeps the card busy, in clock cycles.
+    pub fn with_write_time(mut self, cycles: u64) -> Self {
+        self.write_time = cycles;
+        self
+    }
+    /// The contents of the card.
+    pub fn image(&self) -> &[u8] {
+        &self.image
+    }
+    fn blocks(&self) -> u32 {
+        (self.image.len() / BLOCK_SIZE) as u32
+    }
+    /// The R1 response with the idle bit filled in.
+    fn r1(&self, flags: u8) -> u8 {
+        if self.ready { flags } else { flags | r1::IDLE }
+    }
+    fn on_command(&mut self, frame: &[u8], ctx: &mut SpiContext) {
+        let index = frame[0] & 0x3F;
+        let argument = u32::from_be_bytes([frame[1], frame[2], frame[3], frame[4]]);
+        let app_command = std::mem::take(&mut self.app_command);
+
+        // CMD0 and CMD8 are always checked, as the card may still be in SD mode.
+        let check_crc = self.crc_enabled || index == command::GO_IDLE_STATE || index == command::SEND_IF_COND;
+        // The time between the command and the response.
+        ctx.send_byte(0xFF);
+        if check_crc && crc7(&frame[..5]) != frame[5] {
+            self.trace.warn(TraceEvent::new(format!("Bad CRC on CMD{}.", index)));
+            ctx.send_byte(self.r1(r1::CRC_ERROR));
+            return;
+        }
+
+        if self.trace.enabled(Verbosity::Debug) {
+            self.trace.debug(TraceEvent::new(format!("CMD{} {:#x}", index, argument)));
+        }
+        match (app_command, index) {
+            (false, command::GO_IDLE_STATE) => {
+                self.ready = false;
+                self.init_polls = self.init_delay;
+                self.crc_enabled = false;
+                ctx.send_byte(r1::IDLE);
+            }
+            (false, command::SEND_IF_COND) => {
+                // Echo the voltage range and the check pattern.
+                ctx.send_byte(self.r1(0));
+                for byte in [0x00, 0x00, (argument >> 8) as u8 & 0x0F, argument as u8] {
+                    ctx.send_byte(byte);
+                }
+            }
+            (false, command::APP_CMD) => {
+                self.app_command = true;
+                ctx.send_byte(self.r1(0));
+            }
+            (true, command::SD_SEND_OP_COND) => {
+                if self.init_polls > 0 {
+                    self.init_polls -= 1;
+                }
+                self.ready = self.init_polls == 0;
+                ctx.send_byte(self.r1(0));
+            }
+            (false, command::READ_OCR) => {
+                let ocr = if self.ready { OCR_POWER_UP | OCR_CCS } else { 0 } | 0x00FF_8000;
+                ctx.send_byte(self.r1(0));
+                for byte in ocr.to_be_bytes() {
+                    ctx.send_byte(byte);
+                }
+            }
+            (false, command::CRC_ON_OFF) => {
+                self.crc_enabled = argument & 1 != 0;
+                ctx.send_byte(self.r1(0));
+            }
+            (false, command::SET_BLOCKLEN) if self.ready => {
+                // High capacity cards only do 512 byte blocks.
+                ctx.send_byte(if argument as usize == BLOCK_SIZE { 0 } else { r1::PARAMETER_ERROR });
+            }
+            (false, command::READ_SINGLE_BLOCK) if self.ready => {
+                if argument >= self.blocks() {
+                    ctx.send_byte(r1::PARAMETER_ERROR);
+                    return;
+                }
+                let start = argument as usize * BLOCK_SIZE;
+                let data = &self.image[start..start + BLOCK_SIZE];
+                ctx.send_byte(0);
+                // The time before the data block.
+                ctx.send_byte(0xFF);
+                ctx.send_byte(START_BLOCK);
+                for byte in data {
+                    ctx.send_byte(*byte);
+                }
+                for byte in crc16(data).to_be_bytes() {
+                    ctx.send_byte(byte);
+                }
+            }
+            (false, command::WRITE_BLOCK) if self.ready => {
+                if argument >= self.blocks() {
+                    ctx.send_byte(r1::PARAMETER_ERROR);
+                    return;
+                }
+                ctx.send_byte(0);
+                self.state = CardState::AwaitToken { block: argument };
+            }
+            _ => {
+                self.trace.warn(TraceEvent::new(format!("Illegal command CMD{}.", index)));
+                ctx.send_byte(self.r1(r1::ILLEGAL_COMMAND));
+            }
+        }
+    }
+    /// Writes a received block, returning the data response token.
+    fn write_block(&mut self, block: u32, data: &[u8]) -> u8 {
+        let (data, crc) = data.split_at(BLOCK_SIZE);
+        if self.crc_enabled && crc16(data).to_be_bytes() != crc {
+            self.trace.warn(TraceEvent::new(format!("Bad CRC on block {}.", block)));
+            return data_response::CRC_ERROR;
+        }
+
+        let start = block as usize * BLOCK_SIZE;
+        self.image[start..start + BLOCK_SIZE].copy_from_slice(data);
+        if let Some(file) = &mut self.file
+            && let Err(error) = file.seek(SeekFrom::Start(start as u64)).and_then(|_| file.write_all(data))
+        {
+            self.trace.error(TraceEvent::new(format!("Failed to write block {} to the image: {}", block, error)));
+            return data_response::WRITE_ERROR;
+        }
+        data_response::ACCEPTED
+    }
+}
+
+impl SpiDevice for SdCard {
+    fn on_word(&mut self, word: u32, ctx: &mut SpiContext) {
+        let byte = word as u8;
+
+        match &mut self.state {
+            CardState::Command(frame) => {
+                // Frames start with a zero start bit followed by a one.
+                if frame.is_empty() && byte & 0xC0 != 0x40 {
+                    return;
+                }
+                frame.push(byte);
+                if frame.len() == 6 {
+                    let frame = std::mem::take(frame);
+                    self.on_command(&frame, ctx);
+                }
+            }
+            CardState::AwaitToken { block } => {
+                if byte == START_BLOCK {
+                    self.state = CardState::Receiving { block: *block, data: vec![] };
+                }
+            }
+            CardState::Receiving { block, data } => {
+                data.push(byte);
+                if data.len() == BLOCK_SIZE + 2 {
+                    let (block, data) = (*block, std::mem::take(data));
+                    let response = self.write_block(block, &data);
+                    ctx.send_byte(response);
+                    self.state = if response == data_response::ACCEPTED {
+                        CardState::Busy { until: ctx.cycles() + self.write_time }
+                    } else {
+                        CardState::Command(vec![])
+                    };
+                }
+            }
+            CardState::Busy { .. } => {}
+        }
+    }
+    fn on_output_empty(&mut self, ctx: &mut SpiContext) {
+        if let CardState::Busy { until } = self.state {
+            if ctx.cycles() < until {
+                // Hold MISO low while busy.
+                ctx.send_byte(0x00);
+            } else {
+                ctx.send_byte(0xFF);
+                self.state = CardState::Command(vec![]);
+            }
+        }
+    }
+    fn on_deselect(&mut self) {
+        // A half sent command is dropped, the rest carries on across chip select.
+        if let CardState::Command(frame) = &mut self.state {
+            frame.clear();
+        }
+    }
+    fn set_tracer(&mut self, tracer: DeviceTracer) {
+        self.trace = tracer;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use crate::spi::{master::SpiMaster, sim::SpiSimulation, slave::SpiSlave};
+
+    use super::{command, command_frame, crc16, r1, SdCard, BLOCK_SIZE, OCR_CCS, START_BLOCK};
+
+    /// Sends a command and returns the response without the leading 0xFF.
+    fn send(sim: &mut SpiSimulation, index: u8, argument: u32, len: usize) -> Vec<u8> {
+        let frame = command_frame(index, argument).map(|b| b as u32).to_vec();
+        sim.transfer(frame, len + 1).into_iter().skip(1).map(|w| w as u8).collect()
+    }
+
+    #[test]
+    pub fn test_crc() {
+        assert_eq!(command_frame(command::GO_IDLE_STATE, 0)[5], 0x95);
+        assert_eq!(command_frame(command::SEND_IF_COND, 0x1AA)[5], 0x87);
+        assert_eq!(crc16(&[0xFF; 512]), 0x7FA1);
+    }
+
+    #[test]
+    pub fn test_sd_card_init_read_write() {
+        let slave = SpiSlave::from_device(SdCard::new(8).with_init_polls(3));
+        let mut sim = SpiMaster::new().simulate(slave, Duration::from_micros(1));
+
+        assert_eq!(send(&mut sim, command::GO_IDLE_STATE, 0, 1), vec![r1::IDLE]);
+        assert_eq!(send(&mut sim, command::SEND_IF_COND, 0x1AA, 5), vec![r1::IDLE, 0x00, 0x00, 0x01, 0xAA]);
+        // Reads are refused until the card is initialized.
+        assert_eq!(send(&mut sim, command::READ_SINGLE_BLOCK, 0, 1), vec![r1::IDLE | r1::ILLEGAL_COMMAND]);
+
+        let mut polls = 0;
+        loop {
+            send(&mut sim, command::APP_CMD, 0, 1);
+            polls += 1;
+            if send(&mut sim, command::SD_SEND_OP_COND, 1 << 30, 1) == vec![0x00] {
+                break;
+            }
+        }
+        assert_eq!(polls, 3);
+        let ocr = send(&mut sim, command::READ_OCR, 0, 5);
+        assert_eq!(ocr[0], 0x00);
+        assert_ne!(u32::from_be_bytes([ocr[1], ocr[2], ocr[3], ocr[4]]) & OCR_CCS, 0);
+
+        // Write block 3.
+        let block: Vec<u8> = (0..BLOCK_SIZE).map(|i| i as u8).collect();
+        assert_eq!(send(&mut sim, command::WRITE_BLOCK, 3, 1), vec![0x00]);
+        let mut packet = vec![START_BLOCK as u32];
+        packet.extend(block.iter().map(|b| *b as u32));
+        packet.extend(crc16(&block).to_be_bytes().map(|b| b as u32));
+        assert_eq!(sim.transfer(packet, 1), vec![0x05]);
+        let mut busy = 0;
+        while sim.read_words(1) != vec![0xFF] {
+            busy += 1;
+        }
+        assert!(busy > 0);
+
+        // Read it back.
+        let response = send(&mut sim, command::READ_SINGLE_BLOCK, 3, 3 + BLOCK_SIZE + 2);
+        assert_eq!(&response[..3], &[0x00, 0xFF, START_BLOCK]);
+        assert_eq!(&response[3..3 + BLOCK_SIZE], &block[..]);
+        assert_eq!(&response[3 + BLOCK_SIZE..], &crc16(&block).to_be_bytes());
+
+        assert_eq!(send(&mut sim, command::READ_SINGLE_BLOCK, 8, 1), vec![r1::PARAMETER_ERROR]);
+
+        // Once CRCs are turned on a corrupted frame is rejected.
+        assert_eq!(send(&mut sim, command::CRC_ON_OFF, 1, 1), vec![0x00]);
+        let mut frame = command_frame(command::READ_SINGLE_BLOCK, 3).map(|b| b as u32).to_vec();
+        frame[5] ^= 0x02;
+        assert_eq!(sim.transfer(frame, 2)[1] as u8, r1::CRC_ERROR);
+    }
+
+    #[test]
+    pub fn test_sd_card_idles_high() {
+        let slave = SpiSlave::from_device(SdCard::new(8));
+        let mut sim = SpiMaster::new().simulate(slave, Duration::from_micros(1));
+
+        // The dummy bytes clocked between commands read as 0xFF.
+        assert_eq!(sim.read_words(4), vec![0xFF; 4]);
+        assert_eq!(send(&mut sim, command::GO_IDLE_STATE, 0, 1), vec![r1::IDLE]);
+        assert_eq!(sim.transfer(vec![0xFF; 4], 4), vec![0xFF; 4]);
+        assert_eq!(send(&mut sim, command::SEND_IF_COND, 0x1AA, 5), vec![r1::IDLE, 0x00, 0x00, 0x01, 0xAA]);
+        assert_eq!(sim.read_words(2), vec![0xFF; 2]);
+    }
+}
@@ -0,0 +1,154 @@
+use std::{sync::Arc, time::Duration};
+
+use super::{
+    master::{Completion, Disconnected, MasterStepper, SpiMaster},
+    slave::{SlaveStepper, SpiSlave},
+    transaction::SpiTransaction,
+    wire::SpiMedium,
+};
+
+/// A single threaded SPI link where the clock advances in virtual time.
+///
+/// Both sides are executed in lock-step on the calling thread, so runs are
+/// reproducible and do not spend any real time sleeping.
+pub struct SpiSimulation {
+    /// The wires shared between the master and the slave.
+    medium: SpiMedium,
+    /// The master side of the link.
+    master: MasterStepper,
+    /// The slave side of the link.
+    slave: SlaveStepper,
+    /// The virtual time between two clock ticks.
+    half_period: Duration,
+    /// The virtual time that has passed so far.
+    elapsed: Duration
+}
+
+impl SpiSimulation {
+    /// Creates a new simulation, `clock_speed` is the virtual time
+    /// between two clock ticks.
+    pub fn new(master: SpiMaster<Disconnected>, slave: SpiSlave<Disconnected>, clock_speed: Duration) -> Self {
+        Self {
+            medium: SpiMedium::new(),
+            master: master.into_stepper(),
+            slave: slave.into_stepper(),
+            half_period: clock_speed,
+            elapsed: Duration::ZERO
+        }
+    }
+    /// Advances the clock by a single tick.
+    pub fn step(&mut self) {
+        self.medium.gated_tick();
+        self.elapsed += self.half_period;
+
+        let clock = self.medium.clock.get_line_value();
+        self.slave.on_clock(&self.medium, clock);
+        self.master.on_clock(&self.medium, clock);
+        // The master may stop the clock for a while.
+        self.elapsed += self.master.take_delay();
+    }
+    /// Advances the clock by the given amount of ticks.
+    pub fn run(&mut self, ticks: usize) {
+        for _ in 0..ticks {
+            self.step();
+        }
+    }
+    /// The virtual time that has passed since the simulation started.
+    pub fn elapsed(&self) -> Duration {
+        self.elapsed
+    }
+    /// The wires of the simulated link.
+    pub fn medium(&self) -> &SpiMedium {
+        &self.medium
+    }
+    /// Ends the simulation and hands back both parties.
+    pub fn finish(self) -> (SpiMaster<Disconnected>, SpiSlave<Disconnected>) {
+        (self.master.into_master(), self.slave.into_slave())
+    }
+    /// Writes to a register, stepping the clock until the write went out.
+    pub fn write_register(&mut self, reg: u32, bytes: Vec<u8>) {
+        let waker = self.master.inner().queue_write(reg, bytes, 0);
+        self.run_until(&waker);
+    }
+    /// Reads a register, stepping the clock until the bytes came back.
+    pub fn read_register(&mut self, reg: u32, bytes: usize) -> Vec<u8> {
+        let waker = self.master.inner().queue_read(reg, bytes, 0);
+        self.run_until(&waker);
+        self.master.inner().take_read(&waker, bytes).unwrap()
+    }
+    /// Writes raw frames without a command header.
+    pub fn write_words(&mut self, words: Vec<u32>) {
+        self.transfer(words, 0);
+    }
+    /// Reads raw frames without sending a command header.
+    pub fn read_words(&mut self, count: usize) -> Vec<u32> {
+        self.transfer(vec![], count)
+    }
+    /// Writes raw frames and then reads `read` frames back while keeping CS low.
+    pub fn transfer(&mut self, write: Vec<u32>, read: usize) -> Vec<u32> {
+        let waker = self.master.inner().queue_transfer(write, read, 0);
+        self.run_until(&waker);
+        self.master.inner().take_words(&waker, read).unwrap()
+    }
+    /// Runs a [SpiTransaction], returning the frames of every read operation.
+    pub fn execute(&mut self, transaction: &SpiTransaction) -> Vec<Vec<u32>> {
+        let waker = self.master.inner().queue_transaction(transaction, 0);
+        self.run_until(&waker);
+        self.master.inner().take_transaction(&waker, transaction).unwrap()
+    }
+    /// Steps the clock until the master signals the request is done.
+    fn run_until(&mut self, waker: &Arc<Completion>) {
+        while !waker.is_done() {
+            self.step();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{collections::HashMap, time::Duration};
+
+    use crate::{core::Register, spi::{master::SpiMaster, slave::SpiSlave, wire::Level}};
+
+    fn run() -> (Vec<u8>, Duration) {
+        let slave = SpiSlave::new(HashMap::from([
+            (0x15, Register::new_writeable())
+        ]));
+        let mut sim = SpiMaster::new().simulate(slave, Duration::from_secs(1));
+
+        sim.write_register(0x15, vec![ 0x21 ]);
+        let value = sim.read_register(0x15, 1);
+        (value, sim.elapsed())
+    }
+
+    #[test]
+    pub fn test_simulation_is_deterministic() {
+        let (value, elapsed) = run();
+        assert_eq!(value, vec![ 0x21 ]);
+
+        // Virtual seconds pass instantly and the same every time.
+        assert_eq!(run(), (value, elapsed));
+        assert!(elapsed >= Duration::from_secs(2 * 32));
+    }
+
+    #[test]
+    pub fn test_slave_releases_miso() {
+        let slave = SpiSlave::new(HashMap::from([
+            (0x15, Register::new_writeable())
+        ]));
+        let mut sim = SpiMaster::new().simulate(slave, Duration::from_secs(1));
+
+        sim.write_register(0x15, vec![ 0xFF ]);
+        assert_eq!(sim.read_register(0x15, 1), vec![ 0xFF ]);
+
+        // The slave stops driving MISO as soon as CS goes high and
+        // the pull-up takes over.
+        assert!(sim.medium().cs_select.read());
+        assert_eq!(sim.medium().miso.level(), Level::High);
+
+        // And stays off it while deselected.
+        sim.run(4);
+        assert_eq!(sim.medium().miso.level(), Level::High);
+        assert!(sim.medium().miso.contentions().is_empty());
+    }
+}
@@ -1,158 +1,245 @@
 use std::{
-    collections::HashMap, marker::PhantomData, sync::{Arc, Mutex}
+    any::Any, collections::HashMap, marker::PhantomData, sync::{Arc, Mutex}, thread::JoinHandle
 };
 
 use crate::
-    core::{Port, Register}
+    core::{DeviceId, Port, Register, Tracer}
 ;
 
-use super::{master::{Connected, Disconnected}, wire::SpiMedium};
+use super::{clock::ClockListener, device::{RegisterDevice, SpiContext, SpiDevice}, error::SpiError, fault::SpiFault, framing::{SpiFraming, SpiLanes}, master::{Connected, Disconnected}, wire::{Driver, SpiMedium}};
 
 
 
 pub struct SpiSlave<S> {
     inner: Arc<SpiSlaveInner>,
-    _type: PhantomData<S>
+    _type: PhantomData<S>,
+    /// The thread listening on the medium while connected.
+    thread: Option<JoinHandle<()>>,
+    /// The wires the slave listens on while connected.
+    medium: Option<Arc<SpiMedium>>
 }
 
 struct SpiSlaveInner {
-    registers: Mutex<HashMap<u8, Register>>,
+    device: Mutex<Box<dyn SpiDevice>>,
     port: Mutex<Port>,
     output: Mutex<Port>,
-
+    /// The width of a frame in bits.
+    word_bits: usize
 }
 
 impl SpiSlave<Disconnected> {
-    pub fn new(registers: HashMap<u8, Register>) -> Self {
+    pub fn new(registers: HashMap<u32, Register>) -> Self {
+        Self::with_framing(registers, SpiFraming::default())
+    }
+    /// Creates a slave that decodes commands with the given [SpiFraming].
+    pub fn with_framing(registers: HashMap<u32, Register>, framing: SpiFraming) -> Self {
+        Self::from_device(RegisterDevice::new(registers, framing))
+    }
+    /// Creates a slave that runs the given [SpiDevice].
+    pub fn from_device(device: impl SpiDevice + 'static) -> Self {
+        let word_bits = device.word_bits();
+        assert!((4..=32).contains(&word_bits), "Frames must be between 4 and 32 bits wide.");
         Self {
             inner: Arc::new(SpiSlaveInner {
-                registers: registers.into(),
+                device: Mutex::new(Box::new(device)),
                 port: Mutex::new(Port::new()),
                 output: Mutex::new(Port::new()),
+                word_bits
             }),
-            _type: PhantomData
+            _type: PhantomData,
+            thread: None,
+            medium: None
         }
     }
+    /// Reports what the device is doing to the given [Tracer], as
+    /// [DeviceId::Spi] with the given number.
+    pub fn with_tracer(self, tracer: &Tracer, id: u32) -> Self {
+        self.inner.device.lock().unwrap().set_tracer(tracer.device(DeviceId::Spi(id)));
+        self
+    }
+    /// Lets the slave switch the medium into 3-wire mode, see
+    /// [RegisterDevice::with_three_wire_bit].
+    ///
+    /// Fails with [SpiError::Unsupported] if the slave does not run a [RegisterDevice].
+    pub fn with_three_wire_bit(self, register: u32, mask: u8) -> Result<Self, SpiError> {
+        let mut guard = self.inner.device.lock().unwrap();
+        let any: &mut dyn Any = &mut **guard;
+        let Some(device) = any.downcast_mut::<RegisterDevice>() else {
+            return Err(SpiError::Unsupported);
+        };
+        device.set_three_wire_bit(register, mask);
+        drop(guard);
+        Ok(self)
+    }
     pub fn accept_medium(self, medium: &Arc<SpiMedium>) -> SpiSlave<Connected> {
-        std::thread::spawn({
+        self.accept_medium_on(medium, 0)
+    }
+    /// Connects the slave to the given chip select line of a medium shared
+    /// with other slaves.
+    pub fn accept_medium_on(self, medium: &Arc<SpiMedium>, cs: usize) -> SpiSlave<Connected> {
+        let inner = self.inner.clone();
+        // Attach before the thread starts so not a single edge is missed.
+        let listener = medium.clock.attach();
+        let thread = std::thread::spawn({
             let medium = medium.clone();
-            let inner = self.inner.clone();
-            move || handle_medium(medium, inner)
+            let mut stepper = self.into_stepper();
+            stepper.cs = cs;
+            move || handle_medium(medium, listener, stepper)
         });
 
-        SpiSlave { inner: self.inner, _type: PhantomData }
+        SpiSlave { inner, _type: PhantomData, thread: Some(thread), medium: Some(medium.clone()) }
+    }
+    /// Turns the slave into a stepper that is driven by the caller
+    /// instead of a thread.
+    pub(crate) fn into_stepper(self) -> SlaveStepper {
+        // Drop anything left over from a previous connection.
+        self.inner.device.lock().unwrap().on_deselect();
+        self.inner.port.lock().unwrap().clear();
+        self.inner.output.lock().unwrap().clear();
+        SlaveStepper {
+            inner: self.inner,
+            cs: 0,
+            lanes: SpiLanes::Single,
+            selected: false,
+            previous_value: false
+        }
+    }
+}
+
+impl SpiSlave<Connected> {
+    /// Checks if the slave listens on the given medium.
+    pub(crate) fn is_on(&self, medium: &Arc<SpiMedium>) -> bool {
+        self.medium.as_ref().is_some_and(|m| Arc::ptr_eq(m, medium))
+    }
+    /// Waits for the slave thread to exit, the master must have
+    /// pulled the kill line for this to return.
+    pub(crate) fn join(mut self) -> SpiSlave<Disconnected> {
+        if let Some(thread) = self.thread.take() {
+            // A panic in the slave thread has already been reported.
+            let _ = thread.join();
+        }
+        SpiSlave { inner: self.inner, _type: PhantomData, thread: None, medium: None }
+    }
+}
+
+/// Runs the slave side of the protocol one clock phase at a time.
+pub(crate) struct SlaveStepper {
+    inner: Arc<SpiSlaveInner>,
+    /// The chip select line the slave listens to.
+    cs: usize,
+    /// The lanes the device is currently using.
+    lanes: SpiLanes,
+    /// If chip select was low on the previous rising edge.
+    selected: bool,
+    /// Lets us do rising edge detection.
+    previous_value: bool
+}
+
+impl SlaveStepper {
+    /// Turns the stepper back into a disconnected slave.
+    pub(crate) fn into_slave(self) -> SpiSlave<Disconnected> {
+        SpiSlave { inner: self.inner, _type: PhantomData, thread: None, medium: None }
+    }
+    /// Called after every clock tick with the new clock level.
+    pub(crate) fn on_clock(&mut self, medium: &SpiMedium, clock: bool) {
+        if medium.slave_vanished() {
+            for wire in medium.data_lines(SpiLanes::Quad, false) {
+                wire.release(Driver::slave(self.cs));
+            }
+            return;
+        }
+        if self.selected && medium.chip_select(self.cs).read() {
+            // The clock stops while chip select is high, so the release
+            // is noticed on whatever edge comes next.
+            on_deselect(medium, &self.inner, self.cs, &mut self.lanes, &mut self.selected);
+        } else if clock && !self.previous_value && !medium.has_fault(SpiFault::DropEdge) {
+            // Rising edge detected.
+            on_rising_edge(medium, &self.inner, self.cs, &mut self.lanes, &mut self.selected);
+        }
+        self.previous_value = clock;
     }
 }
 
-enum SpiSlaveState {
-    Idle,
-    Writing(u8)
+/// Pulls the detached line once the slave thread exits, even when it panics.
+struct DetachGuard(Arc<SpiMedium>);
+
+impl Drop for DetachGuard {
+    fn drop(&mut self) {
+        self.0.detached.pull(true);
+    }
 }
 
-fn handle_medium(medium: Arc<SpiMedium>, inner: Arc<SpiSlaveInner>) {
-    // Lets us do rising edge detection.
-    let mut previous_value = false;
-    let mut state = SpiSlaveState::Idle;
+fn handle_medium(medium: Arc<SpiMedium>, mut listener: ClockListener, mut stepper: SlaveStepper) {
+    let _guard = DetachGuard(medium.clone());
     loop {
-        let clock = medium.clock.get_clock();
+        let clock = listener.get_clock();
 
         if medium.kill.read() {
             // Kill the slave.
             break;
         }
 
-        if clock && !previous_value {
-            // Rising edge detected.
-            on_rising_edge(&medium, &inner, &mut state);
-        }
-        previous_value = clock;
+        stepper.on_clock(&medium, clock);
     }
 }
 
-fn on_rising_edge(medium: &SpiMedium, inner: &SpiSlaveInner, state: &mut SpiSlaveState) {
+fn on_rising_edge(medium: &SpiMedium, inner: &SpiSlaveInner, cs: usize, lanes: &mut SpiLanes, selected: &mut bool) {
 
-    if medium.cs_select.read() {
-        // Chip select is set to high.
-        *state = SpiSlaveState::Idle;
+    if medium.chip_select(cs).read() {
         return;
     }
+    *selected = true;
+
+    let mut output = inner.output.lock().unwrap();
+    if output.bits_read() == 0 {
+        let mut ctx = SpiContext { medium, output: &mut output, lanes, word_bits: inner.word_bits };
+        inner.device.lock().unwrap().on_output_empty(&mut ctx);
+    }
 
     // If there is a bit to send out, we should send it.
-    if inner.output.lock().unwrap().bits_read() > 0 {
-        // Writes the output buffe.r
-        medium.miso.pull(inner.output.lock().unwrap().read().unwrap());
+    if output.bits_read() > 0 {
+        // Writes the output buffer.
+        for wire in medium.data_lines(*lanes, false) {
+            wire.drive(Driver::slave(cs), output.read().unwrap_or(false));
+        }
     } else {
         // Read the MOSI line.
-        read_mosi(inner, medium, state);        
+        read_mosi(inner, medium, &mut output, lanes);
+    }
+}
+
+/// Drops whatever was in flight once chip select goes high and lets go of
+/// the data lines so other slaves can use them.
+fn on_deselect(medium: &SpiMedium, inner: &SpiSlaveInner, cs: usize, lanes: &mut SpiLanes, selected: &mut bool) {
+    for wire in medium.data_lines(SpiLanes::Quad, false) {
+        wire.release(Driver::slave(cs));
     }
+    *selected = false;
+    inner.device.lock().unwrap().on_deselect();
+    *lanes = SpiLanes::Single;
+    inner.port.lock().unwrap().clear();
+    inner.output.lock().unwrap().clear();
 }
 
-/// Writes the current buffer to MOSI.
+/// Samples the data lines, handing every complete frame to the device.
 fn read_mosi(
     inner: &SpiSlaveInner,
     medium: &SpiMedium,
-    state: &mut SpiSlaveState
+    output: &mut Port,
+    lanes: &mut SpiLanes
 ) {
-    // Store the bit.
+    // Store the bits.
     let mut port_lock = inner.port.lock().unwrap();
-    port_lock.write(medium.mosi.read());
+    for wire in medium.data_lines(*lanes, true) {
+        port_lock.write(wire.read());
+    }
 
     // We only read if we are not sending.
-    // If we have read an entire byte, then we can send it out.
-    if port_lock.bits_read() == 8 {
-        let value = port_lock.read_byte().unwrap();
+    // If we have read an entire frame, then we can hand it off.
+    if port_lock.bits_read() >= inner.word_bits {
+        let value = port_lock.read_word(inner.word_bits).unwrap();
         drop(port_lock);
-        handle_byte_read(inner, value, state);
-    }
-}
-
-fn handle_byte_read(
-    inner: &SpiSlaveInner,
-    value: u8,
-    state: &mut SpiSlaveState
-) {
-    println!("Byte read {:08b}", value);
-
-    match state {
-        SpiSlaveState::Idle => {
-            let register = value & 0b00111111;
-            if value & 0x80 != 0 {
-                // Read call.
-                println!("Read call for register: {:#x}", register);
-                if let Some(register) = inner.registers.lock().unwrap().get_mut(&register) {
-                    register.start_read();
-
-                    while !register.is_done() {
-
-                        // Write the register.
-                        let rb = register.read_bit().unwrap();
-                        println!("rb: {}", rb);
-                        inner.output.lock().unwrap().write(rb);
-                    }
-
-                    register.finish_read();
-      
-                } else {
-                    println!("No such register exists.");
-                }
-            } else {
-                println!("Write call for register: {:#x}", register);
-                if let Some(rgstr) = inner.registers.lock().unwrap().get_mut(&register) {
-                    rgstr.start_write();
-                    *state = SpiSlaveState::Writing(register);
-                } else {
-                    println!("No such register exists.");
-                }
-                
-            }
-        }
-        SpiSlaveState::Writing(register) => {
-            println!("Writing byte {:#x} to register [{:#x}]", value, *register);
-            if let Some(register) = inner.registers.lock().unwrap().get_mut(register) {
-                register.write_byte(value);
-            } else {
-                println!("No such register exists.");
-            }
-        }
+        let mut ctx = SpiContext { medium, output, lanes, word_bits: inner.word_bits };
+        inner.device.lock().unwrap().on_word(value, &mut ctx);
     }
 }
@@ -0,0 +1,53 @@
+use std::time::Duration;
+
+/// A single step of a [SpiTransaction].
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum SpiOperation {
+    /// Writes raw frames.
+    Write(Vec<u32>),
+    /// Reads the given amount of frames.
+    Read(usize),
+    /// Stops the clock for a while.
+    Delay(Duration)
+}
+
+/// A list of operations carried out under a single CS assertion.
+///
+/// Nothing else gets onto the wire while a transaction runs, so a write
+/// followed by a read can not be split up by another request.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct SpiTransaction {
+    operations: Vec<SpiOperation>
+}
+
+impl SpiTransaction {
+    pub fn new() -> Self {
+        Self::default()
+    }
+    /// Writes raw frames.
+    pub fn write(mut self, words: Vec<u32>) -> Self {
+        self.operations.push(SpiOperation::Write(words));
+        self
+    }
+    /// Reads `count` frames, they come back as their own entry in the results.
+    pub fn read(mut self, count: usize) -> Self {
+        self.operations.push(SpiOperation::Read(count));
+        self
+    }
+    /// Stops the clock while keeping CS low.
+    pub fn delay(mut self, delay: Duration) -> Self {
+        self.operations.push(SpiOperation::Delay(delay));
+        self
+    }
+    /// The operations in the order they are carried out.
+    pub fn operations(&self) -> &[SpiOperation] {
+        &self.operations
+    }
+    /// The amount of frames read by each read operation.
+    pub(crate) fn reads(&self) -> impl Iterator<Item = usize> + '_ {
+        self.operations.iter().filter_map(|op| match op {
+            SpiOperation::Read(count) => Some(*count),
+            _ => None
+        })
+    }
+}
@@ -0,0 +1,106 @@
+use std::time::Duration;
+
+use crate::core::LogicTrace;
+
+use super::{probe::{SpiProbe, SpiSample}, wire::Level};
+
+/// Captures SCLK, MOSI, MISO and CS of a [SpiMedium](super::wire::SpiMedium),
+/// one sample per clock phase.
+///
+/// Attach it with [SpiMedium::add_probe](super::wire::SpiMedium::add_probe)
+/// behind an `Arc<Mutex<_>>` and export the [LogicTrace] it builds. A floating
+/// data line reads as low and a floating chip select as deselected.
+pub struct SpiWaveform {
+    trace: LogicTrace,
+    /// The edge the first sample followed.
+    origin: Option<u64>
+}
+
+impl SpiWaveform {
+    /// The channel of SCLK in the trace.
+    pub const SCLK: usize = 0;
+    /// The channel of MOSI in the trace.
+    pub const MOSI: usize = 1;
+    /// The channel of MISO in the trace.
+    pub const MISO: usize = 2;
+    /// The channel of CS in the trace.
+    pub const CS: usize = 3;
+    const CHANNELS: [&str; 4] = ["SCLK", "MOSI", "MISO", "CS"];
+
+    /// Creates a waveform for a link where the clock ticks every `half_period`.
+    pub fn new(half_period: Duration) -> Self {
+        Self {
+            trace: LogicTrace::new("spi", &Self::CHANNELS, half_period),
+            origin: None
+        }
+    }
+    /// The lines so far.
+    pub fn trace(&self) -> &LogicTrace {
+        &self.trace
+    }
+    /// Takes the lines so far and starts over.
+    pub fn take(&mut self) -> LogicTrace {
+        let empty = LogicTrace::new("spi", &Self::CHANNELS, self.trace.period());
+        self.origin = None;
+        std::mem::replace(&mut self.trace, empty)
+    }
+}
+
+impl SpiProbe for SpiWaveform {
+    fn on_sample(&mut self, sample: &SpiSample) {
+        let high = |level: Level| (level == Level::High) as u32;
+        let value = (sample.clock as u32) << Self::SCLK
+            | high(sample.mosi.level) << Self::MOSI
+            | high(sample.miso.level) << Self::MISO
+            | ((sample.cs != Level::Low) as u32) << Self::CS;
+
+        // Edges the probe missed repeat the last sample.
+        let origin = *self.origin.get_or_insert(sample.edge);
+        let index = (sample.edge - origin) as usize;
+        if let Some(&last) = self.trace.samples().last() {
+            while self.trace.len() < index {
+                self.trace.push(last);
+            }
+        }
+        self.trace.push(value);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{collections::HashMap, sync::{Arc, Mutex}, time::Duration};
+
+    use crate::{core::Register, spi::{master::SpiMaster, slave::SpiSlave}};
+
+    use super::SpiWaveform;
+
+    #[test]
+    pub fn test_waveform() {
+        let slave = SpiSlave::new(HashMap::from([
+            (0x10, Register::new_writeable())
+        ]));
+        let mut sim = SpiMaster::new().simulate(slave, Duration::from_nanos(250));
+        let waveform = Arc::new(Mutex::new(SpiWaveform::new(Duration::from_nanos(250))));
+        sim.medium().add_probe(waveform.clone());
+
+        sim.write_register(0x10, vec![ 0xA5 ]);
+        sim.run(4);
+
+        let waveform = waveform.lock().unwrap();
+        let trace = waveform.trace();
+        // MOSI is sampled on the rising edges while CS is low.
+        let bits: Vec<bool> = (1..trace.len())
+            .filter(|&i| !trace.level(i, SpiWaveform::CS))
+            .filter(|&i| !trace.level(i - 1, SpiWaveform::SCLK) && trace.level(i, SpiWaveform::SCLK))
+            .map(|i| trace.level(i, SpiWaveform::MOSI))
+            .collect();
+        let bytes: Vec<u8> = bits.chunks(8)
+            .map(|byte| byte.iter().fold(0, |value, &bit| value << 1 | bit as u8))
+            .collect();
+        assert_eq!(bytes.last(), Some(&0xA5));
+
+        let mut vcd = vec![];
+        trace.write_vcd(&mut vcd).unwrap();
+        assert!(String::from_utf8(vcd).unwrap().contains("$var wire 1 $ CS $end"));
+    }
+}
@@ -1,25 +1,155 @@
-use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{atomic::{AtomicBool, Ordering}, Mutex};
 
-use super::clock::Clock;
+use crate::core::FaultPlan;
 
+use super::{clock::Clock, error::SpiError, fault::{SpiFault, SpiFaults}, framing::SpiLanes, probe::{LineSample, SpiProbe, SpiSample}};
+
+/// Identifies who is driving a [LiveWire].
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct Driver(pub u32);
+
+impl Driver {
+    /// The driver used by [LiveWire::pull], this is the master on SPI links.
+    pub const PRIMARY: Driver = Driver(0);
+    /// The slave on a SPI link, or the first one when there are several.
+    pub const SLAVE: Driver = Driver(1);
+
+    /// The slave on the given chip select line.
+    pub fn slave(cs: usize) -> Driver {
+        Driver(Self::SLAVE.0 + cs as u32)
+    }
+    /// Checks if this is one of the slaves.
+    pub fn is_slave(self) -> bool {
+        self.0 >= Self::SLAVE.0
+    }
+}
+
+/// The level a [LiveWire] resolves to.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Level {
+    Low,
+    High,
+    /// Nobody is driving the wire and there is no bias.
+    HighZ
+}
+
+/// What an undriven [LiveWire] settles to.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Default)]
+pub enum Bias {
+    /// The wire floats.
+    #[default]
+    None,
+    PullUp,
+    PullDown
+}
+
+/// Two drivers fighting over a wire.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Contention {
+    /// The driver that caused the contention.
+    pub driver: Driver,
+    /// The value it tried to drive.
+    pub value: bool,
+    /// The driver already driving the opposite value.
+    pub other: Driver
+}
+
+/// A wire that any amount of drivers can drive or release.
+///
+/// When drivers disagree the wire reads low and a [Contention] is recorded.
 pub struct LiveWire {
-    signal: AtomicBool
+    state: Mutex<WireState>
+}
+
+struct WireState {
+    /// The drivers currently driving the wire and their values.
+    drivers: Vec<(Driver, bool)>,
+    bias: Bias,
+    /// Every contention seen so far.
+    contentions: Vec<Contention>,
+    /// The level the wire is held at no matter who drives it.
+    forced: Option<bool>
 }
 
 impl LiveWire {
     pub fn new() -> Self {
+        Self::with_bias(Bias::None)
+    }
+    /// Creates a wire that settles to the given [Bias] when undriven.
+    pub fn with_bias(bias: Bias) -> Self {
         Self {
-            signal: AtomicBool::default()
+            state: Mutex::new(WireState {
+                drivers: vec![],
+                bias,
+                contentions: vec![],
+                forced: None
+            })
         }
     }
+    /// Drives the wire from the primary driver to the opposite of the level
+    /// it currently resolves to.
     pub fn flip(&self) {
-        self.signal.fetch_not(Ordering::Relaxed);
+        let value = self.read();
+        self.pull(!value);
     }
+    /// Drives the wire from the primary driver.
     pub fn pull(&self, signal: bool) {
-        self.signal.store(signal, Ordering::Relaxed);
+        self.drive(Driver::PRIMARY, signal);
+    }
+    /// Drives the wire to a value.
+    pub fn drive(&self, driver: Driver, signal: bool) {
+        let mut state = self.state.lock().unwrap();
+        state.drivers.retain(|(d, _)| *d != driver);
+        if let Some((other, _)) = state.drivers.iter().find(|(_, v)| *v != signal).copied() {
+            state.contentions.push(Contention { driver, value: signal, other });
+        }
+        state.drivers.push((driver, signal));
     }
+    /// Stops driving the wire, putting the driver in high impedance.
+    pub fn release(&self, driver: Driver) {
+        self.state.lock().unwrap().drivers.retain(|(d, _)| *d != driver);
+    }
+    /// The drivers currently driving the wire.
+    pub fn drivers(&self) -> Vec<Driver> {
+        self.state.lock().unwrap().drivers.iter().map(|(d, _)| *d).collect()
+    }
+    /// Checks if a driver is currently driving the wire.
+    pub fn is_driven_by(&self, driver: Driver) -> bool {
+        self.state.lock().unwrap().drivers.iter().any(|(d, _)| *d == driver)
+    }
+    /// Holds the wire at a level no matter who drives it, like a fault
+    /// would. `None` hands the wire back to its drivers.
+    pub fn force(&self, level: Option<bool>) {
+        self.state.lock().unwrap().forced = level;
+    }
+    /// Resolves the level of the wire.
+    pub fn level(&self) -> Level {
+        let state = self.state.lock().unwrap();
+        match state.forced {
+            Some(true) => return Level::High,
+            Some(false) => return Level::Low,
+            None => {}
+        }
+        if state.drivers.is_empty() {
+            return match state.bias {
+                Bias::None => Level::HighZ,
+                Bias::PullUp => Level::High,
+                Bias::PullDown => Level::Low
+            };
+        }
+        if state.drivers.iter().all(|(_, v)| *v) {
+            Level::High
+        } else {
+            Level::Low
+        }
+    }
+    /// Reads the wire, a floating wire reads low.
     pub fn read(&self) -> bool {
-        self.signal.load(Ordering::Relaxed)
+        self.level() == Level::High
+    }
+    /// Every contention recorded on this wire so far.
+    pub fn contentions(&self) -> Vec<Contention> {
+        self.state.lock().unwrap().contentions.clone()
     }
 }
 
@@ -30,9 +160,208 @@ impl Default for LiveWire {
 }
 
 pub struct SpiMedium {
+    /// Also known as IO0.
     pub mosi: LiveWire,
+    /// Also known as IO1, pulled up so an idle slave reads as 0xFF.
     pub miso: LiveWire,
+    /// The third data line, only used by quad transfers.
+    pub io2: LiveWire,
+    /// The fourth data line, only used by quad transfers.
+    pub io3: LiveWire,
+    /// The chip select of the first slave.
     pub cs_select: LiveWire,
+    /// The chip selects of any further slaves.
+    more_cs: Vec<LiveWire>,
     pub kill: LiveWire,
-    pub clock: Clock
-}
\ No newline at end of file
+    /// Pulled high once the slave stops listening.
+    pub detached: LiveWire,
+    pub clock: Clock,
+    /// If MOSI and MISO are folded into one bidirectional SDIO line.
+    three_wire: AtomicBool,
+    /// The first fault reported by the slave since the master last looked.
+    fault: Mutex<Option<SpiError>>,
+    /// Everything watching the wires.
+    probes: Mutex<Vec<Box<dyn SpiProbe>>>,
+    /// The faults to inject, if any.
+    faults: Mutex<Option<SpiFaults>>
+}
+
+impl SpiMedium {
+    /// Creates a new medium with the CS line pulled high, so
+    /// no slave is selected until the master is ready.
+    pub fn new() -> Self {
+        Self::with_chip_selects(1)
+    }
+    /// Creates a new medium with a CS line for every slave, all pulled high.
+    pub fn with_chip_selects(count: usize) -> Self {
+        let medium = Self {
+            clock: Clock::new(),
+            cs_select: LiveWire::new(),
+            more_cs: (1..count).map(|_| LiveWire::new()).collect(),
+            miso: LiveWire::with_bias(Bias::PullUp),
+            mosi: LiveWire::new(),
+            io2: LiveWire::with_bias(Bias::PullUp),
+            io3: LiveWire::with_bias(Bias::PullUp),
+            kill: LiveWire::new(),
+            detached: LiveWire::new(),
+            three_wire: AtomicBool::new(false),
+            fault: Mutex::new(None),
+            probes: Mutex::default(),
+            faults: Mutex::default()
+        };
+        medium.cs_select.pull(true);
+        for line in &medium.more_cs {
+            line.pull(true);
+        }
+        medium
+    }
+    /// The chip select line of a slave, the first one is [SpiMedium::cs_select].
+    pub fn chip_select(&self, index: usize) -> &LiveWire {
+        match index {
+            0 => &self.cs_select,
+            _ => &self.more_cs[index - 1]
+        }
+    }
+    /// The amount of chip select lines.
+    pub fn chip_selects(&self) -> usize {
+        1 + self.more_cs.len()
+    }
+    /// Pulls a chip select line high. The slave lets go of the data lines on
+    /// that very edge, like output drivers gated by CS do.
+    pub fn deselect(&self, cs: usize) {
+        self.chip_select(cs).pull(true);
+        for wire in self.data_lines(SpiLanes::Quad, false) {
+            wire.release(Driver::slave(cs));
+        }
+    }
+    /// Checks if any slave is selected.
+    pub fn is_selected(&self) -> bool {
+        (0..self.chip_selects()).any(|cs| !self.chip_select(cs).read())
+    }
+    /// The wires carrying data in one direction, in the order the bits go out.
+    ///
+    /// Single transfers use MOSI or MISO depending on the direction, dual and
+    /// quad transfers put the most significant bit on the highest IO line.
+    /// In 3-wire mode both directions share MOSI as the SDIO line.
+    pub fn data_lines(&self, lanes: SpiLanes, from_master: bool) -> Vec<&LiveWire> {
+        match lanes {
+            SpiLanes::Single if from_master || self.is_three_wire() => vec![&self.mosi],
+            SpiLanes::Single => vec![&self.miso],
+            SpiLanes::Dual => vec![&self.miso, &self.mosi],
+            SpiLanes::Quad => vec![&self.io3, &self.io2, &self.miso, &self.mosi]
+        }
+    }
+    /// Switches between 4-wire and 3-wire (half-duplex SDIO) mode.
+    pub fn set_three_wire(&self, enabled: bool) {
+        self.three_wire.store(enabled, Ordering::SeqCst);
+    }
+    /// Checks if the medium is in 3-wire mode.
+    pub fn is_three_wire(&self) -> bool {
+        self.three_wire.load(Ordering::SeqCst)
+    }
+    /// Reports a fault to the master, only the first fault is kept.
+    pub fn report(&self, fault: SpiError) {
+        self.fault.lock().unwrap().get_or_insert(fault);
+    }
+    /// Takes the reported fault.
+    pub fn take_fault(&self) -> Option<SpiError> {
+        self.fault.lock().unwrap().take()
+    }
+    /// Attaches a probe, it gets a [SpiSample] of the wires every clock phase.
+    pub fn add_probe(&self, probe: impl SpiProbe + 'static) {
+        self.probes.lock().unwrap().push(Box::new(probe));
+    }
+    /// Injects faults into the link from now on, see [SpiFault].
+    pub fn set_faults(&self, faults: FaultPlan<SpiFault>) {
+        *self.faults.lock().unwrap() = Some(SpiFaults::new(faults));
+    }
+    /// Every fault injected so far, with the clock cycle it fired on.
+    pub fn fired_faults(&self) -> Vec<(SpiFault, u64)> {
+        self.faults.lock().unwrap().as_ref().map(|f| f.plan.fired().to_vec()).unwrap_or_default()
+    }
+    /// Checks if a fault fired on the current clock cycle.
+    pub(crate) fn has_fault(&self, fault: SpiFault) -> bool {
+        self.faults.lock().unwrap().as_ref().is_some_and(|f| f.current.contains(&fault))
+    }
+    /// Checks if the slave vanished.
+    pub(crate) fn slave_vanished(&self) -> bool {
+        self.faults.lock().unwrap().as_ref().is_some_and(|f| f.vanished)
+    }
+    /// Ticks the clock, first handing the settled wires to the probes.
+    pub fn tick(&self) {
+        self.clock.tick_with(|edge, clock| self.settle(edge, clock));
+    }
+    /// Lets the listeners and probes see another edge while the clock stays
+    /// where it is.
+    pub fn idle(&self) {
+        self.clock.idle_with(|edge, clock| self.settle(edge, clock));
+    }
+    /// Ticks the clock like a master that gates it, it stays low while no
+    /// slave is selected.
+    pub(crate) fn gated_tick(&self) {
+        if self.is_selected() || self.clock.get_line_value() {
+            self.tick();
+        } else {
+            self.idle();
+        }
+    }
+    /// Injects the faults and samples the wires before the clock moves on.
+    fn settle(&self, edge: u64, clock: bool) {
+        if let Some(faults) = self.faults.lock().unwrap().as_mut() {
+            faults.inject(self, clock);
+        }
+        let mut probes = self.probes.lock().unwrap();
+        if probes.is_empty() {
+            return;
+        }
+        let line = |wire: &LiveWire| LineSample {
+            level: wire.level(),
+            driver: wire.drivers().first().copied()
+        };
+        let sample = SpiSample {
+            edge,
+            clock,
+            cs: if self.more_cs.iter().any(|line| !line.read()) { Level::Low } else { self.cs_select.level() },
+            mosi: line(&self.mosi),
+            miso: line(&self.miso),
+            io2: line(&self.io2),
+            io3: line(&self.io3)
+        };
+        for probe in probes.iter_mut() {
+            probe.on_sample(&sample);
+        }
+    }
+}
+
+impl Default for SpiMedium {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Bias, Contention, Driver, Level, LiveWire};
+
+    #[test]
+    pub fn test_wire_resolution() {
+        let wire = LiveWire::new();
+        assert_eq!(wire.level(), Level::HighZ);
+        assert!(!wire.read());
+
+        let wire = LiveWire::with_bias(Bias::PullUp);
+        assert_eq!(wire.level(), Level::High);
+
+        wire.drive(Driver::SLAVE, false);
+        assert_eq!(wire.level(), Level::Low);
+
+        // A second slave that never let go of the line.
+        wire.drive(Driver(2), true);
+        assert_eq!(wire.level(), Level::Low);
+        assert_eq!(wire.contentions(), vec![Contention { driver: Driver(2), value: true, other: Driver::SLAVE }]);
+
+        wire.release(Driver::SLAVE);
+        wire.release(Driver(2));
+        assert_eq!(wire.level(), Level::High);
+    }
+}
@@ -0,0 +1,48 @@
+//! Fixtures shared by the tests, a BMI270 IMU as it shows up on either bus.
+
+use std::{collections::HashMap, sync::{Arc, Mutex}, time::Duration};
+
+use crate::{
+    core::Register,
+    i2c::{I2CSlave, I2cRecorder, Master},
+    spi::{framing::SpiFraming, master::{Disconnected, SpiMaster}, record::SpiRecorder, sim::SpiSimulation, slave::SpiSlave}
+};
+
+/// What the BMI270 answers on its CHIP_ID register.
+pub(crate) fn chip_id() -> Vec<u8> {
+    vec![ 0x24 ]
+}
+
+/// A BMI270 at I2C address 0x68 with CHIP_ID at 0x00 and PWR_CTRL at 0x7D.
+pub(crate) fn i2c_bmi270() -> I2CSlave {
+    let mut slave = I2CSlave::new(0x68);
+    slave.create_register(0x00, Register::new_read_only(chip_id));
+    slave.create_register(0x7D, Register::new_writeable());
+    slave
+}
+
+/// A master with an [i2c_bmi270] on its bus and a recorder listening in.
+pub(crate) fn i2c_bmi270_recorded() -> (Master, Arc<Mutex<I2cRecorder>>) {
+    let mut master = Master::new();
+    master.add_device(i2c_bmi270());
+    let recorder = Arc::new(Mutex::new(I2cRecorder::new()));
+    master.add_probe(recorder.clone());
+    (master, recorder)
+}
+
+/// The same registers behind a SPI slave, framed like the real chip.
+pub(crate) fn spi_bmi270() -> SpiSlave<Disconnected> {
+    SpiSlave::with_framing(HashMap::from([
+        (0x00, Register::new_read_only(chip_id)),
+        (0x7D, Register::new_writeable())
+    ]), SpiFraming::bmi270())
+}
+
+/// Simulates a link to the slave with a BMI270 framed master and a
+/// recorder on the medium.
+pub(crate) fn spi_recorded(slave: SpiSlave<Disconnected>) -> (SpiSimulation, Arc<Mutex<SpiRecorder>>) {
+    let sim = SpiMaster::with_framing(SpiFraming::bmi270()).simulate(slave, Duration::from_micros(1));
+    let recorder = Arc::new(Mutex::new(SpiRecorder::new(SpiFraming::bmi270())));
+    sim.medium().add_probe(recorder.clone());
+    (sim, recorder)
+}